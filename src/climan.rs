@@ -1,12 +1,42 @@
+pub mod aws;
+pub mod cache;
+pub mod cookies;
+pub mod data;
+pub mod dns;
+pub mod fixtures;
+pub mod har;
+pub mod import;
+pub mod interrupt;
+pub mod json_log;
+pub mod junit;
 pub mod model;
+pub mod oauth;
+pub mod repeat;
 pub mod request;
+pub mod spec;
+pub mod template;
 pub mod workflow;
 
 #[cfg(test)]
 mod tests {
-    use crate::climan::workflow::Workflow;
+    use crate::climan::cache::ResponseCache;
+    use crate::climan::data::{csv_records, csv_rows, load_records, record_variables};
+    use crate::climan::fixtures::Fixtures;
+    use crate::climan::import::{curl_to_request, postman_to_workflow, shell_words, to_yaml};
+    use crate::climan::interrupt::Interrupt;
+    use crate::climan::junit::JunitReport;
+    use crate::climan::model::{Authentication, Body, HeaderValue, Method};
+    use crate::climan::repeat::{repeat, RepeatStats};
+    use crate::climan::request::{Request, Response};
+    use crate::climan::spec;
+    use crate::climan::template::Templates;
+    use crate::climan::workflow::{load_variable_files, Workflow, WorkflowResult};
     use httpmock::prelude::*;
+    use httpmock::Mock;
     use std::collections::HashMap;
+    use std::path::Path;
+    use std::rc::Rc;
+    use std::time::Duration;
     use test_log::test;
 
     #[test(tokio::test)]
@@ -35,7 +65,14 @@ mod tests {
         let client = reqwest::Client::new();
         let workflow: Workflow = serde_yaml::from_str(&test_spec)?;
         let result = workflow
-            .execute(&client, HashMap::new(), None, &|_, _| (), &|_, _, _| ())
+            .execute(
+                &client,
+                &Templates::new(false),
+                HashMap::new(),
+                None,
+                &|_, _| (),
+                &|_, _, _| (),
+            )
             .await;
         match result {
             Ok(_) => (),
@@ -45,4 +82,881 @@ mod tests {
         }
         Ok(())
     }
+
+    #[test(tokio::test)]
+    async fn should_continue_on_error() -> anyhow::Result<()> {
+        let server = MockServer::start();
+
+        server.mock(|when, then| {
+            when.method(GET).path("/status/500");
+            then.status(500);
+        });
+
+        server.mock(|when, then| {
+            when.method(GET).path("/get");
+            then.status(200).body("ok");
+        });
+
+        let test_spec = include_str!("../tests/continue_on_error.yaml").replace(
+            "https://postman-echo.com",
+            format!("http://{}:{}", server.host(), server.port()).as_str(),
+        );
+
+        let client = reqwest::Client::new();
+        let workflow: Workflow = serde_yaml::from_str(&test_spec)?;
+        let result = workflow
+            .execute(
+                &client,
+                &Templates::new(false),
+                HashMap::new(),
+                None,
+                &|_, _| (),
+                &|_, _, _| (),
+            )
+            .await?;
+
+        assert_eq!(result.failed_steps, vec!["broken".to_string()]);
+        assert_eq!(result.responses.len(), 2);
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn should_evaluate_assertions() -> anyhow::Result<()> {
+        let server = MockServer::start();
+
+        server.mock(|when, then| {
+            when.method(GET).path("/get");
+            then.status(200)
+                .header("content-type", "application/json")
+                .body(r#"{"args": {"value": 1}}"#);
+        });
+
+        let test_spec = include_str!("../tests/assertions.yaml").replace(
+            "https://postman-echo.com",
+            format!("http://{}:{}", server.host(), server.port()).as_str(),
+        );
+
+        let client = reqwest::Client::new();
+        let workflow: Workflow = serde_yaml::from_str(&test_spec)?;
+        let variables = HashMap::from([("expected".to_string(), Some("args".to_string()))]);
+        let result = workflow
+            .execute(
+                &client,
+                &Templates::new(false),
+                variables,
+                None,
+                &|_, _| (),
+                &|_, _, _| (),
+            )
+            .await?;
+
+        assert_eq!(result.failed_steps, vec!["checked".to_string()]);
+        let assertions = &result.responses[0].assertions;
+        assert_eq!(assertions.passed, 3);
+        assert_eq!(
+            assertions.failures,
+            vec!["$.args.value: expected 2, got 1".to_string()]
+        );
+        Ok(())
+    }
+
+    /// mocks the endpoints called by `tests/include.yaml`, returning the mock
+    /// of the included request and of the one after it
+    fn mock_include_endpoints(server: &MockServer) -> (Mock<'_>, Mock<'_>) {
+        let included = server.mock(|when, then| {
+            when.method(POST).path("/post");
+            then.status(200)
+                .header("content-type", "application/json")
+                .header("set-cookie", "session=s3cr3t")
+                .body(include_str!("../tests/echo.json"));
+        });
+        let after = server.mock(|when, then| {
+            when.method(GET).path("/get").query_param("value", "37.4");
+            then.status(200).body("ok");
+        });
+        (included, after)
+    }
+
+    /// executes `tests/include.yaml` against `base_url`
+    async fn execute_include(
+        fixtures: Option<Fixtures>,
+        base_url: String,
+    ) -> anyhow::Result<WorkflowResult> {
+        let mut workflow = Workflow::load(Path::new("tests/include.yaml"))?;
+        workflow.fixtures = fixtures.map(Rc::new);
+        workflow
+            .execute(
+                &reqwest::Client::new(),
+                &Templates::new(false),
+                HashMap::from([("base_url".to_string(), Some(base_url))]),
+                None,
+                &|_, _| (),
+                &|_, _, _| (),
+            )
+            .await
+    }
+
+    #[test(tokio::test)]
+    async fn should_include_workflows() -> anyhow::Result<()> {
+        let server = MockServer::start();
+        let (_, after) = mock_include_endpoints(&server);
+
+        let result = execute_include(None, server.base_url()).await?;
+
+        after.assert();
+        assert_eq!(result.responses.len(), 2);
+        assert_eq!(
+            result.final_variables.get("value"),
+            Some(&Some("37.4".to_string()))
+        );
+        assert!(!result.final_variables.contains_key("previous_body"));
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn should_stop_interrupted_workflows() -> anyhow::Result<()> {
+        let server = MockServer::start();
+        let (included, after) = mock_include_endpoints(&server);
+
+        let (_sender, receiver) = tokio::sync::watch::channel(true);
+        let mut workflow = Workflow::load(Path::new("tests/include.yaml"))?;
+        workflow.interrupt = Some(Interrupt::from(receiver));
+        let result = workflow
+            .execute(
+                &reqwest::Client::new(),
+                &Templates::new(false),
+                HashMap::from([("base_url".to_string(), Some(server.base_url()))]),
+                None,
+                &|_, _| (),
+                &|_, _, _| (),
+            )
+            .await?;
+
+        included.assert_hits(0);
+        after.assert_hits(0);
+        assert!(result.interrupted);
+        assert!(result.responses.is_empty());
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn should_replay_recorded_responses() -> anyhow::Result<()> {
+        let server = MockServer::start();
+        let (mut included, mut after) = mock_include_endpoints(&server);
+
+        let dir = std::env::temp_dir().join(format!("climan-fixtures-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        execute_include(Some(Fixtures::record_to(dir.clone())), server.base_url()).await?;
+        included.delete();
+        after.delete();
+
+        // responses are matched by request name, so the url may change
+        let result = execute_include(
+            Some(Fixtures::replay_from(dir.clone())),
+            "http://replayed.invalid".to_string(),
+        )
+        .await;
+        let recorded = std::fs::read_to_string(dir.join("included-7f0f70e6.json"));
+        std::fs::remove_dir_all(&dir)?;
+
+        let result = result?;
+        assert_eq!(result.responses.len(), 2);
+        assert_eq!(
+            result.final_variables.get("value"),
+            Some(&Some("37.4".to_string()))
+        );
+        assert!(!recorded?.contains("s3cr3t"));
+        Ok(())
+    }
+
+    #[test]
+    fn should_record_similar_request_names_apart() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("climan-names-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let recorder = Fixtures::record_to(dir.clone());
+        for name in ["get user", "get_user"] {
+            let response = Response {
+                body: name.to_string(),
+                ..Default::default()
+            };
+            recorder.record(name, "GET", "http://localhost/user", &response)?;
+        }
+
+        let replayer = Fixtures::replay_from(dir.clone());
+        let spaced = replayer.replay("get user", "GET");
+        let underscored = replayer.replay("get_user", "GET");
+        std::fs::remove_dir_all(&dir)?;
+        assert_eq!(spaced?.body, "get user");
+        assert_eq!(underscored?.body, "get_user");
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn should_revalidate_cached_responses() -> anyhow::Result<()> {
+        let server = MockServer::start();
+        let dir = std::env::temp_dir().join(format!("climan-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let request: Request = spec::from_yaml(include_str!("../tests/cached.yaml"))?;
+        let send = |token: &str| {
+            let mut request = request.clone();
+            request.cache = Some(Rc::new(ResponseCache::new(&dir)));
+            let variables = HashMap::from([
+                ("base_url".to_string(), Some(server.base_url())),
+                ("token".to_string(), Some(token.to_string())),
+            ]);
+            async move {
+                request
+                    .execute(
+                        &reqwest::Client::new(),
+                        &Templates::new(false),
+                        &variables,
+                        |_, _| (),
+                        |_, _, _| (),
+                    )
+                    .await
+            }
+        };
+
+        let mut fetched = server.mock(|when, then| {
+            when.method(GET).path("/resource");
+            then.status(200)
+                .header("etag", "\"v1\"")
+                .header("set-cookie", "session=s3cr3t")
+                .body("the resource");
+        });
+        let first = send("alice").await;
+        fetched.assert();
+        fetched.delete();
+
+        let not_modified = server.mock(|when, then| {
+            when.method(GET)
+                .path("/resource")
+                .header("if-none-match", "\"v1\"");
+            then.status(304);
+        });
+        let second = send("alice").await;
+        not_modified.assert();
+
+        // other credentials do not share the cached response, a request sending
+        // the etag would match the mock above
+        let other = server.mock(|when, then| {
+            when.method(GET).path("/resource");
+            then.status(200).body("another resource");
+        });
+        let third = send("bob").await;
+        other.assert();
+
+        let stored = std::fs::read_to_string(dir.join("etags.json"));
+        std::fs::remove_dir_all(&dir)?;
+        assert!(!first?.not_modified);
+        let second = second?;
+        assert!(second.not_modified);
+        assert_eq!(second.status_code, 200);
+        assert_eq!(second.body, "the resource");
+        assert_eq!(third?.body, "another resource");
+        assert!(!stored?.contains("s3cr3t"));
+        Ok(())
+    }
+
+    #[test]
+    fn should_key_cached_responses_with_a_stable_digest() -> anyhow::Result<()> {
+        let url = "http://localhost/resource";
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("accept", "*/*".parse()?);
+        assert_eq!(ResponseCache::key(url, &headers, None), url);
+
+        // the digest must not change between runs or builds
+        headers.insert("authorization", "Bearer alice".parse()?);
+        assert_eq!(
+            ResponseCache::key(url, &headers, None),
+            "http://localhost/resource 1bcf4c4d16fd0da2"
+        );
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn should_repeat_list_query_params() -> anyhow::Result<()> {
+        let server = MockServer::start();
+
+        let mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/list")
+                .query_param("id", "1")
+                .query_param("id", "two");
+            then.status(200);
+        });
+
+        let workflow: Workflow = serde_yaml::from_str(&format!(
+            "name: list\nrequests:\n  - name: list\n    uri: {}\n    method: GET\n    queryParams:\n      id: [1, two]\n",
+            server.url("/list")
+        ))?;
+        workflow
+            .execute(
+                &reqwest::Client::new(),
+                &Templates::new(false),
+                HashMap::new(),
+                None,
+                &|_, _| (),
+                &|_, _, _| (),
+            )
+            .await?;
+        mock.assert();
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn should_send_scalar_header_values() -> anyhow::Result<()> {
+        let server = MockServer::start();
+
+        let mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/headers")
+                .header("x-page-size", "50")
+                .header("x-ratio", "1.5")
+                .header("x-dry-run", "true")
+                .header("x-versions", "1")
+                .header("x-versions", "two");
+            then.status(200);
+        });
+
+        let workflow = Workflow::load(Path::new("tests/headers.yaml"))?;
+        workflow
+            .execute(
+                &reqwest::Client::new(),
+                &Templates::new(false),
+                HashMap::from([("base_url".to_string(), Some(server.base_url()))]),
+                None,
+                &|_, _| (),
+                &|_, _, _| (),
+            )
+            .await?;
+        mock.assert();
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn should_override_variable_files_with_data_records() -> anyhow::Result<()> {
+        let server = MockServer::start();
+
+        let mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/record")
+                .query_param("value", "from the record");
+            then.status(200);
+        });
+
+        let mut variables = HashMap::from([("base_url".to_string(), Some(server.base_url()))]);
+        variables.extend(load_variable_files(vec!["tests/context.yaml".into()]).await?);
+        let records = load_records(Path::new("tests/records.csv"))?;
+        let workflow = Workflow::load(Path::new("tests/record.yaml"))?;
+        for record in records {
+            workflow
+                .execute(
+                    &reqwest::Client::new(),
+                    &Templates::new(false),
+                    record_variables(&variables, record),
+                    None,
+                    &|_, _| (),
+                    &|_, _, _| (),
+                )
+                .await?;
+        }
+        mock.assert();
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn should_load_client_certificate_from_variable_files() -> anyhow::Result<()> {
+        let workflow = Workflow::load(Path::new("tests/mtls.yaml"))?;
+        let templates = Templates::new(false);
+
+        let variables = load_variable_files(vec!["tests/certificate.yaml".into()]).await?;
+        assert!(workflow.identity(&templates, &variables)?.is_some());
+
+        let err = workflow.identity(&templates, &HashMap::new()).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("could not read client certificate file /client.pem"));
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn should_not_batch_requests_reading_the_previous_response() -> anyhow::Result<()> {
+        let server = MockServer::start();
+
+        server.mock(|when, then| {
+            when.method(POST).path("/create");
+            then.status(201).delay(Duration::from_millis(200));
+        });
+        let check = server.mock(|when, then| {
+            when.method(GET).path("/check").query_param("status", "201");
+            then.status(200);
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/list");
+            then.status(200);
+        });
+
+        let workflow = Workflow::load(Path::new("tests/concurrent.yaml"))?;
+        let result = workflow
+            .execute(
+                &reqwest::Client::new(),
+                &Templates::new(false),
+                HashMap::from([("base_url".to_string(), Some(server.base_url()))]),
+                None,
+                &|_, _| (),
+                &|_, _, _| (),
+            )
+            .await?;
+        check.assert();
+        assert_eq!(result.responses.len(), 3);
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn should_send_ndjson_body() -> anyhow::Result<()> {
+        let server = MockServer::start();
+
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/events")
+                .header("content-type", "application/x-ndjson")
+                .body(
+                    "{\"event\":\"login\",\"user\":\"ada\"}\n{\"event\":\"logout\",\"user\":\"ada\"}\n",
+                );
+            then.status(202);
+        });
+
+        let workflow = Workflow::load(Path::new("tests/ndjson.yaml"))?;
+        workflow
+            .execute(
+                &reqwest::Client::new(),
+                &Templates::new(false),
+                HashMap::from([
+                    ("base_url".to_string(), Some(server.base_url())),
+                    ("user".to_string(), Some("ada".to_string())),
+                ]),
+                None,
+                &|_, _| (),
+                &|_, _, _| (),
+            )
+            .await?;
+        mock.assert();
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn should_send_json_body() -> anyhow::Result<()> {
+        let server = MockServer::start();
+
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/orders")
+                .header("content-type", "application/json")
+                .json_body(serde_json::json!({
+                    "customer": "ada",
+                    "items": [
+                        { "sku": "A-1", "quantity": 2 },
+                        { "sku": "B-2", "quantity": 1 }
+                    ],
+                    "express": false,
+                    "note": null
+                }));
+            then.status(201);
+        });
+
+        let workflow = Workflow::load(Path::new("tests/json_body.yaml"))?;
+        workflow
+            .execute(
+                &reqwest::Client::new(),
+                &Templates::new(false),
+                HashMap::from([
+                    ("base_url".to_string(), Some(server.base_url())),
+                    ("customer".to_string(), Some("ada".to_string())),
+                ]),
+                None,
+                &|_, _| (),
+                &|_, _, _| (),
+            )
+            .await?;
+        mock.assert();
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn should_render_graphql_variables() -> anyhow::Result<()> {
+        let server = MockServer::start();
+
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/graphql")
+                .header("content-type", "application/json")
+                .json_body(serde_json::json!({
+                    "query": "query User($id: ID!) { user(id: $id) { name } }",
+                    "operationName": "User",
+                    "variables": { "id": "42", "fields": ["name"] }
+                }));
+            then.status(200);
+        });
+
+        let workflow = Workflow::load(Path::new("tests/graphql.yaml"))?;
+        workflow
+            .execute(
+                &reqwest::Client::new(),
+                &Templates::new(false),
+                HashMap::from([
+                    ("base_url".to_string(), Some(server.base_url())),
+                    ("user_id".to_string(), Some("42".to_string())),
+                    ("field".to_string(), Some("name".to_string())),
+                ]),
+                None,
+                &|_, _| (),
+                &|_, _, _| (),
+            )
+            .await?;
+        mock.assert();
+        Ok(())
+    }
+
+    #[test(tokio::test)]
+    async fn should_keep_types_of_templated_json_fields() -> anyhow::Result<()> {
+        let server = MockServer::start();
+
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/users")
+                .header("content-type", "application/json")
+                .json_body(serde_json::json!({
+                    "id": "42",
+                    "user": { "count": 42, "admin": true, "label": "user 42" }
+                }));
+            then.status(201);
+        });
+
+        let workflow = Workflow::load(Path::new("tests/templated_json.yaml"))?;
+        workflow
+            .execute(
+                &reqwest::Client::new(),
+                &Templates::new(false),
+                HashMap::from([
+                    ("base_url".to_string(), Some(server.base_url())),
+                    ("id".to_string(), Some("42".to_string())),
+                ]),
+                None,
+                &|_, _| (),
+                &|_, _, _| (),
+            )
+            .await?;
+        mock.assert();
+        Ok(())
+    }
+
+    #[test]
+    fn should_write_junit_report() -> anyhow::Result<()> {
+        let report = JunitReport::default();
+        report.passed("orders", "list", Duration::from_millis(120));
+        report.failed(
+            "orders",
+            "create <new>",
+            Duration::from_millis(80),
+            "expected status 201, got 400 & \"bad\"".to_string(),
+        );
+        report.error("orders", "delete", "timed out".to_string());
+        report.skipped("orders", "cleanup");
+
+        let path = std::env::temp_dir().join(format!("climan-junit-{}.xml", std::process::id()));
+        report.write(&path, "orders & co")?;
+        let xml = std::fs::read_to_string(&path);
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(
+            xml?,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites>
+  <testsuite name="orders &amp; co" tests="4" failures="1" errors="1" skipped="1" time="0.200">
+    <testcase name="list" classname="orders" time="0.120"/>
+    <testcase name="create &lt;new&gt;" classname="orders" time="0.080">
+      <failure message="expected status 201, got 400 &amp; &quot;bad&quot;">expected status 201, got 400 &amp; &quot;bad&quot;</failure>
+    </testcase>
+    <testcase name="delete" classname="orders" time="0.000">
+      <error message="timed out">timed out</error>
+    </testcase>
+    <testcase name="cleanup" classname="orders" time="0.000">
+      <skipped/>
+    </testcase>
+  </testsuite>
+</testsuites>
+"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_import_curl_command() -> anyhow::Result<()> {
+        let request = curl_to_request(
+            r#"curl 'https://postman-echo.com/post' \
+              -H 'Content-Type: application/json' \
+              --data-raw '{"value": 1}' \
+              -u user:secret"#,
+        )?;
+
+        assert!(matches!(request.method, Method::Post));
+        assert_eq!(request.uri, "https://postman-echo.com/post");
+        assert!(matches!(
+            request.headers.unwrap().get("Content-Type"),
+            Some(HeaderValue::Single(value)) if value == "application/json"
+        ));
+        assert!(
+            matches!(request.body, Some(Body::Content { content, .. }) if content == r#"{"value": 1}"#)
+        );
+        assert!(matches!(
+            request.authentication,
+            Some(Authentication::Basic { username, password: Some(password) })
+                if username == "user" && password == "secret"
+        ));
+
+        let request = curl_to_request(
+            r#"curl https://postman-echo.com/post \
+              --data-urlencode 'q=a&b=c d' \
+              --data-urlencode '=x y' \
+              -d 'raw=a b'"#,
+        )?;
+        assert!(
+            matches!(request.body, Some(Body::Content { content, .. }) if content == "q=a%26b%3Dc%20d&x%20y&raw=a b")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_import_postman_collection() -> anyhow::Result<()> {
+        let workflow = postman_to_workflow(include_str!("../tests/postman.json"))?;
+        let workflow: Workflow = serde_yaml::from_str(&to_yaml(&workflow)?)?;
+        let workflow = serde_json::to_value(&workflow)?;
+
+        assert_eq!(workflow["name"], "Users API");
+        let create = &workflow["requests"][0];
+        assert_eq!(create["name"], "users / create user");
+        assert_eq!(create["uri"], "{{ base_url }}/users");
+        assert_eq!(create["method"], "Post");
+        assert_eq!(
+            create["headers"],
+            serde_json::json!({ "Content-Type": "application/json" })
+        );
+        assert_eq!(create["body"]["content"], "{\"name\": \"{{ name }}\"}");
+        assert_eq!(
+            create["authentication"],
+            serde_json::json!({ "type": "bearer", "token": "{{ token }}" })
+        );
+
+        let login = &workflow["requests"][1];
+        assert_eq!(login["uri"], "{{ base_url }}/login");
+        assert_eq!(
+            login["body"]["form"],
+            serde_json::json!({ "remember": "true" })
+        );
+        assert_eq!(
+            login["authentication"],
+            serde_json::json!({ "type": "basic", "username": "ada", "password": "{{ password }}" })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_split_shell_words() -> anyhow::Result<()> {
+        let words = shell_words(
+            r#"curl -H "X-Name: \"ada\"" \
+              --data-raw $'a\nb\'c' 'single "quoted"' un\ quoted"#,
+        )?;
+        assert_eq!(
+            words,
+            vec![
+                "curl",
+                "-H",
+                "X-Name: \"ada\"",
+                "--data-raw",
+                "a\nb'c",
+                "single \"quoted\"",
+                "un quoted",
+            ]
+        );
+        assert!(shell_words("curl 'open").is_err());
+        assert!(shell_words("curl \"open").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn should_import_curl_flags() -> anyhow::Result<()> {
+        let request = curl_to_request("curl -sI https://postman-echo.com/get")?;
+        assert!(matches!(request.method, Method::Head));
+
+        let request = curl_to_request("curl -sXPUT -d@user.json https://postman-echo.com/put")?;
+        assert!(matches!(request.method, Method::Put));
+        assert!(matches!(request.body, Some(Body::File { file }) if file == "user.json"));
+
+        let request =
+            curl_to_request("curl -sX PATCH --data-raw @literal https://postman-echo.com")?;
+        assert!(matches!(request.method, Method::Patch));
+        assert!(
+            matches!(request.body, Some(Body::Content { content, .. }) if content == "@literal")
+        );
+
+        let request =
+            curl_to_request("curl -sSL -o out.json -Hx-id:1 https://postman-echo.com/get")?;
+        assert_eq!(request.uri, "https://postman-echo.com/get");
+        assert!(matches!(
+            request.headers.unwrap().get("x-id"),
+            Some(HeaderValue::Single(value)) if value == "1"
+        ));
+
+        let err =
+            curl_to_request("curl -d a=1 -d @user.json https://postman-echo.com/post").unwrap_err();
+        assert!(err.to_string().contains("@user.json"));
+        Ok(())
+    }
+
+    fn row(fields: &[&str]) -> Vec<String> {
+        fields.iter().map(|field| field.to_string()).collect()
+    }
+
+    #[test]
+    fn should_split_csv_rows() -> anyhow::Result<()> {
+        let rows =
+            csv_rows("name,note\r\n\"Doe, Jane\",\"said \"\"hi\"\"\nand left\"\r\n\r\nbob,\n")?;
+        assert_eq!(
+            rows,
+            vec![
+                row(&["name", "note"]),
+                row(&["Doe, Jane", "said \"hi\"\nand left"]),
+                row(&["bob", ""]),
+            ]
+        );
+        assert!(csv_rows("name\n\"open").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_ragged_csv_records() {
+        let err = csv_records("name,age\nada,36\nbob\n").unwrap_err();
+        assert_eq!(err.to_string(), "record 2 has 1 fields, expected 2");
+    }
+
+    #[test]
+    fn should_detect_json_and_csv_data_files() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("climan-data-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let load = |name: &str, content: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, content)?;
+            load_records(&path)
+        };
+        let json = load(
+            "users.json",
+            r#"[{"name": "ada", "age": 36, "team": null}]"#,
+        );
+        let json_content = load("users.txt", r#" [{"name": "ada"}]"#);
+        let csv = load("users.csv", "name,age\nada,36\n");
+        let no_records = load("empty.csv", "name,age\n");
+        let empty_json = load("empty.json", "[]");
+        std::fs::remove_dir_all(&dir)?;
+
+        assert_eq!(
+            json?,
+            vec![HashMap::from([
+                ("name".to_string(), Some("ada".to_string())),
+                ("age".to_string(), Some("36".to_string())),
+                ("team".to_string(), None),
+            ])]
+        );
+        assert_eq!(
+            json_content?,
+            vec![HashMap::from([(
+                "name".to_string(),
+                Some("ada".to_string())
+            )])]
+        );
+        assert_eq!(
+            csv?,
+            vec![HashMap::from([
+                ("name".to_string(), Some("ada".to_string())),
+                ("age".to_string(), Some("36".to_string())),
+            ])]
+        );
+        assert!(no_records
+            .unwrap_err()
+            .to_string()
+            .contains("has no records"));
+        assert!(empty_json
+            .unwrap_err()
+            .to_string()
+            .contains("has no records"));
+        Ok(())
+    }
+
+    fn stats(millis: impl IntoIterator<Item = u64>) -> RepeatStats {
+        RepeatStats {
+            times: millis.into_iter().map(Duration::from_millis).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_compute_nearest_rank_percentiles() {
+        assert_eq!(stats([]).percentile(50), None);
+
+        let single = stats([42]);
+        for percentile in [0, 50, 99, 100] {
+            assert_eq!(
+                single.percentile(percentile),
+                Some(Duration::from_millis(42))
+            );
+        }
+
+        let hundred = stats(1..=100);
+        assert_eq!(hundred.percentile(50), Some(Duration::from_millis(50)));
+        assert_eq!(hundred.percentile(95), Some(Duration::from_millis(95)));
+        assert_eq!(hundred.percentile(99), Some(Duration::from_millis(99)));
+        assert_eq!(hundred.percentile(100), Some(Duration::from_millis(100)));
+    }
+
+    #[test(tokio::test)]
+    async fn should_repeat_requests() -> anyhow::Result<()> {
+        let server = MockServer::start();
+
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/ping");
+            then.status(503);
+        });
+
+        let request: Request = spec::from_yaml(include_str!("../tests/ping.yaml"))?;
+        let stats = repeat(
+            &request,
+            &reqwest::Client::new(),
+            &Templates::new(false),
+            &HashMap::from([("base_url".to_string(), Some(server.base_url()))]),
+            5,
+            2,
+        )
+        .await;
+
+        mock.assert_hits(5);
+        assert_eq!(stats.sent, 5);
+        assert_eq!(stats.succeeded, 0);
+        assert_eq!(stats.errors, 0);
+        assert_eq!(stats.statuses.get(&503), Some(&5));
+        assert!(stats.percentile(99).is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn should_suggest_misspelled_fields() {
+        let err = spec::from_yaml::<Request>(
+            "name: test\nuri: http://localhost\nmethod: GET\nquaryParams:\n  id: 1\n",
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "unknown field `quaryParams` at line 4 column 1, did you mean `queryParams`?"
+        );
+    }
 }
@@ -0,0 +1,85 @@
+use std::{str::FromStr, time::SystemTime};
+
+use anyhow::anyhow;
+use aws_credential_types::Credentials;
+use aws_sigv4::{
+    http_request::{
+        sign, PayloadChecksumKind, PercentEncodingMode, SignableBody, SignableRequest,
+        SigningSettings, UriPathNormalizationMode,
+    },
+    sign::v4,
+};
+use reqwest::header::{HeaderName, HeaderValue};
+
+/// signs a fully built request with AWS Signature Version 4, adding the
+/// signature headers. Streamed bodies (multipart) are sent as unsigned payloads.
+pub fn sign_request(
+    request: &mut reqwest::Request,
+    access_key: &str,
+    secret_key: &str,
+    session_token: Option<&str>,
+    region: &str,
+    service: &str,
+) -> anyhow::Result<()> {
+    let sign_error = |err: &dyn std::fmt::Display| anyhow!("could not sign request: {}", err);
+
+    let mut settings = SigningSettings::default();
+    // S3 expects the payload hash header and paths encoded only once
+    if service == "s3" {
+        settings.payload_checksum_kind = PayloadChecksumKind::XAmzSha256;
+        settings.percent_encoding_mode = PercentEncodingMode::Single;
+        settings.uri_path_normalization_mode = UriPathNormalizationMode::Disabled;
+    }
+
+    let identity = Credentials::new(
+        access_key,
+        secret_key,
+        session_token.map(str::to_string),
+        None,
+        "climan",
+    )
+    .into();
+    let params = v4::SigningParams::builder()
+        .identity(&identity)
+        .region(region)
+        .name(service)
+        .time(SystemTime::now())
+        .settings(settings)
+        .build()
+        .map_err(|err| sign_error(&err))?
+        .into();
+
+    let headers = request
+        .headers()
+        .iter()
+        .map(|(name, value)| {
+            value
+                .to_str()
+                .map(|value| (name.as_str(), value))
+                .map_err(|err| sign_error(&err))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let body = match request.body() {
+        Some(body) => body
+            .as_bytes()
+            .map_or(SignableBody::UnsignedPayload, SignableBody::Bytes),
+        None => SignableBody::Bytes(&[]),
+    };
+    let signable = SignableRequest::new(
+        request.method().as_str(),
+        request.url().as_str(),
+        headers.into_iter(),
+        body,
+    )
+    .map_err(|err| sign_error(&err))?;
+    let (instructions, _) = sign(signable, &params)
+        .map_err(|err| sign_error(&err))?
+        .into_parts();
+
+    for (name, value) in instructions.headers() {
+        request
+            .headers_mut()
+            .insert(HeaderName::from_str(name)?, HeaderValue::from_str(value)?);
+    }
+    Ok(())
+}
@@ -0,0 +1,156 @@
+use std::{
+    cell::{RefCell, RefMut},
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::request::Response;
+
+const CACHE_FILE: &str = "etags.json";
+
+/// request headers holding credentials, requests sending different ones do not
+/// share their cached responses
+const CREDENTIAL_HEADERS: [&str; 4] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "x-api-key",
+];
+
+/// responses with an `ETag` kept between runs by url and credentials, so that
+/// unchanged resources are not downloaded again
+#[derive(Debug)]
+pub struct ResponseCache {
+    path: PathBuf,
+    /// the entries of the cache file, read on first use
+    entries: RefCell<Option<HashMap<String, CachedResponse>>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CachedResponse {
+    etag: String,
+    status_code: u16,
+    headers: HashMap<String, String>,
+    body: String,
+}
+
+impl ResponseCache {
+    pub fn new(dir: &Path) -> ResponseCache {
+        ResponseCache {
+            path: dir.join(CACHE_FILE),
+            entries: RefCell::default(),
+        }
+    }
+
+    /// the key of the responses to a request: its url, followed by a digest of
+    /// the credentials it sends when there are some, which stays the same
+    /// between runs. `api_key_header` is the header of the request api key, if any.
+    pub fn key(url: &str, headers: &HeaderMap, api_key_header: Option<&str>) -> String {
+        let mut credentials: Vec<(String, &[u8])> = headers
+            .iter()
+            .filter(|(name, _)| {
+                CREDENTIAL_HEADERS.contains(&name.as_str())
+                    || api_key_header
+                        .is_some_and(|header| name.as_str().eq_ignore_ascii_case(header))
+            })
+            .map(|(name, value)| (name.as_str().to_string(), value.as_bytes()))
+            .collect();
+        if credentials.is_empty() {
+            return url.to_string();
+        }
+        credentials.sort();
+        let mut hasher = Sha256::new();
+        for (name, value) in credentials {
+            hasher.update(name.as_bytes());
+            hasher.update(b":");
+            hasher.update(value);
+            hasher.update(b"\n");
+        }
+        let digest = format!("{:x}", hasher.finalize());
+        format!("{} {}", url, &digest[..16])
+    }
+
+    fn entries(&self) -> anyhow::Result<RefMut<'_, HashMap<String, CachedResponse>>> {
+        let mut entries = self.entries.borrow_mut();
+        if entries.is_none() {
+            *entries = Some(self.load()?);
+        }
+        Ok(RefMut::map(entries, |entries| {
+            entries.get_or_insert_with(HashMap::new)
+        }))
+    }
+
+    fn load(&self) -> anyhow::Result<HashMap<String, CachedResponse>> {
+        if !self.path.exists() {
+            return Ok(HashMap::new());
+        }
+        let content = std::fs::read_to_string(&self.path)
+            .map_err(|err| anyhow!("could not read cache {}: {}", self.path.display(), err))?;
+        serde_json::from_str(&content)
+            .map_err(|err| anyhow!("could not parse cache {}: {}", self.path.display(), err))
+    }
+
+    /// the etag of the response kept for the key
+    pub fn etag(&self, key: &str) -> anyhow::Result<Option<String>> {
+        Ok(self.entries()?.get(key).map(|cached| cached.etag.clone()))
+    }
+
+    /// replaces a `304 Not Modified` response with the one kept for the key,
+    /// other responses are returned as they are
+    pub fn revalidate(&self, key: &str, response: Response) -> anyhow::Result<Response> {
+        if response.status_code != 304 {
+            return Ok(response);
+        }
+        let entries = self.entries()?;
+        let Some(cached) = entries.get(key) else {
+            return Ok(response);
+        };
+        Ok(Response {
+            status_code: cached.status_code,
+            headers: cached.headers.clone(),
+            body_size: cached.body.len(),
+            body: cached.body.clone(),
+            not_modified: true,
+            ..response
+        })
+    }
+
+    /// keeps the successful text responses with an etag, without their
+    /// cookies. The ones without an etag anymore are forgotten.
+    pub fn store(&self, key: &str, response: &Response) -> anyhow::Result<()> {
+        if response.not_modified || !(200..300).contains(&response.status_code) {
+            return Ok(());
+        }
+        let mut entries = self.entries()?;
+        let etag = response.headers.get("etag");
+        match etag {
+            Some(etag) if response.binary_body.is_none() && response.saved_to.is_none() => {
+                let headers = response
+                    .headers
+                    .iter()
+                    .filter(|(name, _)| !name.eq_ignore_ascii_case("set-cookie"))
+                    .map(|(name, value)| (name.clone(), value.clone()))
+                    .collect();
+                entries.insert(
+                    key.to_string(),
+                    CachedResponse {
+                        etag: etag.clone(),
+                        status_code: response.status_code,
+                        headers,
+                        body: response.body.clone(),
+                    },
+                );
+            }
+            _ if entries.remove(key).is_none() => return Ok(()),
+            _ => {}
+        }
+        std::fs::write(&self.path, serde_json::to_string(&*entries)?)
+            .map_err(|err| anyhow!("could not write cache {}: {}", self.path.display(), err))
+    }
+}
@@ -0,0 +1,71 @@
+use std::{
+    io::{BufReader, BufWriter},
+    path::Path,
+    sync::RwLock,
+};
+
+use anyhow::anyhow;
+use cookie_store::{CookieStore, RawCookie};
+use reqwest::{header::HeaderValue, Url};
+
+/// cookie store shared by all the requests, can be loaded from and saved to a json file
+#[derive(Default)]
+pub struct CookieJar(RwLock<CookieStore>);
+
+impl CookieJar {
+    /// loads the jar from `path`, a missing file results in an empty jar
+    pub fn load(path: &Path) -> anyhow::Result<CookieJar> {
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(CookieJar::default())
+            }
+            Err(err) => {
+                return Err(anyhow!(
+                    "could not read cookie jar {}: {}",
+                    path.display(),
+                    err
+                ))
+            }
+        };
+        let store = CookieStore::load_json(BufReader::new(file))
+            .map_err(|err| anyhow!("could not load cookie jar {}: {}", path.display(), err))?;
+        Ok(CookieJar(RwLock::new(store)))
+    }
+
+    /// saves all the cookies, session ones included, so that they are available to the next run
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let file = std::fs::File::create(path)
+            .map_err(|err| anyhow!("could not write cookie jar {}: {}", path.display(), err))?;
+        self.0
+            .read()
+            .unwrap()
+            .save_incl_expired_and_nonpersistent_json(&mut BufWriter::new(file))
+            .map_err(|err| anyhow!("could not write cookie jar {}: {}", path.display(), err))
+    }
+}
+
+impl reqwest::cookie::CookieStore for CookieJar {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        let cookies = cookie_headers
+            .filter_map(|value| value.to_str().ok())
+            .filter_map(|value| RawCookie::parse(value.to_string()).ok());
+        self.0.write().unwrap().store_response_cookies(cookies, url);
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        let cookies = self
+            .0
+            .read()
+            .unwrap()
+            .get_request_values(url)
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        if cookies.is_empty() {
+            return None;
+        }
+        HeaderValue::from_str(&cookies).ok()
+    }
+}
@@ -0,0 +1,116 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::anyhow;
+
+/// loads the records of a data file: a json array of objects, or a csv file
+/// whose first line holds the field names
+pub fn load_records(path: &Path) -> anyhow::Result<Vec<HashMap<String, Option<String>>>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|err| anyhow!("could not read data file {}: {}", path.display(), err))?;
+    let is_json = path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+        || content.trim_start().starts_with('[');
+    let records = if is_json {
+        json_records(&content)
+    } else {
+        csv_records(&content)
+    };
+    let records =
+        records.map_err(|err| anyhow!("could not parse data file {}: {}", path.display(), err))?;
+    if records.is_empty() {
+        return Err(anyhow!("data file {} has no records", path.display()));
+    }
+    Ok(records)
+}
+
+/// the variables of the run of a record: its fields override `variables`
+pub fn record_variables(
+    variables: &HashMap<String, Option<String>>,
+    record: HashMap<String, Option<String>>,
+) -> HashMap<String, Option<String>> {
+    let mut variables = variables.clone();
+    variables.extend(record);
+    variables
+}
+
+fn json_records(content: &str) -> anyhow::Result<Vec<HashMap<String, Option<String>>>> {
+    let records: Vec<serde_json::Map<String, serde_json::Value>> = serde_json::from_str(content)?;
+    Ok(records
+        .into_iter()
+        .map(|record| {
+            record
+                .into_iter()
+                .map(|(name, value)| {
+                    let value = match value {
+                        serde_json::Value::Null => None,
+                        serde_json::Value::String(value) => Some(value),
+                        value => Some(value.to_string()),
+                    };
+                    (name, value)
+                })
+                .collect()
+        })
+        .collect())
+}
+
+pub(crate) fn csv_records(content: &str) -> anyhow::Result<Vec<HashMap<String, Option<String>>>> {
+    let mut rows = csv_rows(content)?.into_iter();
+    let Some(names) = rows.next() else {
+        return Ok(Vec::new());
+    };
+    rows.enumerate()
+        .map(|(index, row)| {
+            if row.len() != names.len() {
+                return Err(anyhow!(
+                    "record {} has {} fields, expected {}",
+                    index + 1,
+                    row.len(),
+                    names.len()
+                ));
+            }
+            Ok(names
+                .iter()
+                .cloned()
+                .zip(row.into_iter().map(Some))
+                .collect())
+        })
+        .collect()
+}
+
+/// splits csv content into rows of fields, fields can be quoted with `"` to
+/// hold commas, line breaks and `""` escaped quotes. Empty lines are skipped.
+pub(crate) fn csv_rows(content: &str) -> anyhow::Result<Vec<Vec<String>>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => row.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                if !row.is_empty() || !field.is_empty() {
+                    row.push(std::mem::take(&mut field));
+                    rows.push(std::mem::take(&mut row));
+                }
+            }
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(anyhow!("unterminated quoted field"));
+    }
+    if !row.is_empty() || !field.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
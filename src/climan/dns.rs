@@ -0,0 +1,54 @@
+use std::{
+    cell::Cell,
+    future::Future,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+
+tokio::task_local! {
+    /// time of the lookup made for the request being sent, see `timed`
+    static LOOKUP: Cell<Option<Duration>>;
+}
+
+/// system resolver timing the lookups, they fail after `timeout`
+#[derive(Debug, Default)]
+pub struct TimedResolver {
+    pub timeout: Option<Duration>,
+}
+
+impl Resolve for TimedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let timeout = self.timeout;
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let start = Instant::now();
+            let lookup = tokio::net::lookup_host((host.clone(), 0));
+            let addrs = match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, lookup).await.map_err(|_| {
+                    format!("dns lookup of {} timed out after {:?}", host, timeout)
+                })??,
+                None => lookup.await?,
+            };
+            // lookups outside of `timed`, e.g. for connections the client
+            // finishes in the background, are not reported
+            let _ = LOOKUP.try_with(|lookup| lookup.set(Some(start.elapsed())));
+            let addrs: Vec<SocketAddr> = addrs.collect();
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// runs the sending of a request, along with the time taken to resolve its
+/// host. There is none when a connection was reused or the host is an ip
+/// address.
+pub async fn timed<F: Future>(send: F) -> (F::Output, Option<Duration>) {
+    LOOKUP
+        .scope(Cell::new(None), async {
+            let output = send.await;
+            (output, LOOKUP.with(Cell::get))
+        })
+        .await
+}
@@ -0,0 +1,214 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::request::Response;
+
+/// response headers whose values are not written to the fixtures
+const SENSITIVE_HEADERS: [&str; 5] = [
+    "set-cookie",
+    "cookie",
+    "authorization",
+    "proxy-authorization",
+    "x-api-key",
+];
+const REDACTED: &str = "<redacted>";
+
+/// directory responses are recorded to, or replayed from instead of sending
+/// the requests. Responses are matched by request name, method and the number
+/// of times the request was already sent with that method during the run, so
+/// that urls built with `uuid()` or `now()` still replay.
+#[derive(Debug)]
+pub struct Fixtures {
+    dir: PathBuf,
+    replay: bool,
+    /// requests sent so far, by request name and method
+    sent: RefCell<HashMap<(String, String), usize>>,
+}
+
+/// a recorded response, the extracted variables and assertion results are
+/// computed again when it is replayed
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Fixture {
+    method: String,
+    /// how many times the request was sent with this method before
+    #[serde(default)]
+    occurrence: usize,
+    /// the url the response was recorded for, it is not matched on replay
+    url: String,
+    status_code: u16,
+    http_version: String,
+    #[serde(default)]
+    tls: bool,
+    headers: HashMap<String, String>,
+    body: String,
+    /// base64 encoded body of binary responses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    binary_body: Option<String>,
+    body_size: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dns_ms: Option<u64>,
+    time_to_headers_ms: u64,
+    time_total_ms: u64,
+}
+
+impl Fixtures {
+    pub fn record_to(dir: PathBuf) -> Fixtures {
+        Fixtures {
+            dir,
+            replay: false,
+            sent: RefCell::default(),
+        }
+    }
+
+    pub fn replay_from(dir: PathBuf) -> Fixtures {
+        Fixtures {
+            dir,
+            replay: true,
+            sent: RefCell::default(),
+        }
+    }
+
+    /// the file holding the responses of a request: its name with the
+    /// characters other than letters, digits and `-` replaced by `_`, followed
+    /// by a digest of the name, so that names differing only by those
+    /// characters do not share a file
+    fn path(&self, request: &str) -> PathBuf {
+        let name: String = request
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let digest = format!("{:x}", Sha256::digest(request.as_bytes()));
+        self.dir.join(format!("{}-{}.json", name, &digest[..8]))
+    }
+
+    fn load(path: &Path) -> anyhow::Result<Vec<Fixture>> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| anyhow!("could not read fixture {}: {}", path.display(), err))?;
+        serde_json::from_str(&content)
+            .map_err(|err| anyhow!("could not parse fixture {}: {}", path.display(), err))
+    }
+
+    /// counts a request being sent, returning how many times it was sent before
+    fn next_occurrence(&self, request: &str, method: &str) -> usize {
+        let mut sent = self.sent.borrow_mut();
+        let count = sent
+            .entry((request.to_string(), method.to_string()))
+            .or_default();
+        *count += 1;
+        *count - 1
+    }
+
+    pub fn replays(&self) -> bool {
+        self.replay
+    }
+
+    /// the next response recorded for the request and method, without the
+    /// extracted variables and assertion results
+    pub fn replay(&self, request: &str, method: &str) -> anyhow::Result<Response> {
+        if !self.replay {
+            return Err(anyhow!("responses are not being replayed"));
+        }
+        let occurrence = self.next_occurrence(request, method);
+        let path = self.path(request);
+        let fixture = Fixtures::load(&path)?
+            .into_iter()
+            .find(|fixture| fixture.method == method && fixture.occurrence == occurrence)
+            .ok_or_else(|| {
+                anyhow!(
+                    "no response recorded for {} {} #{} in {}",
+                    method,
+                    request,
+                    occurrence + 1,
+                    path.display()
+                )
+            })?;
+        let binary_body = fixture
+            .binary_body
+            .map(|body| STANDARD.decode(body))
+            .transpose()
+            .map_err(|err| anyhow!("could not decode fixture {}: {}", path.display(), err))?;
+
+        Ok(Response {
+            status_code: fixture.status_code,
+            http_version: fixture.http_version,
+            tls: fixture.tls,
+            dns: fixture.dns_ms.map(Duration::from_millis),
+            time_to_headers: Duration::from_millis(fixture.time_to_headers_ms),
+            time_total: Duration::from_millis(fixture.time_total_ms),
+            headers: fixture.headers,
+            body: fixture.body,
+            binary_body,
+            body_size: fixture.body_size,
+            ..Default::default()
+        })
+    }
+
+    /// saves the response, replacing the one previously recorded for the
+    /// same request, method and occurrence. Sensitive headers are redacted.
+    pub fn record(
+        &self,
+        request: &str,
+        method: &str,
+        url: &str,
+        response: &Response,
+    ) -> anyhow::Result<()> {
+        if self.replay {
+            return Ok(());
+        }
+        let occurrence = self.next_occurrence(request, method);
+        let path = self.path(request);
+        let mut fixtures = if path.exists() {
+            Fixtures::load(&path)?
+        } else {
+            Vec::new()
+        };
+        fixtures.retain(|fixture| fixture.method != method || fixture.occurrence != occurrence);
+        let headers = response
+            .headers
+            .iter()
+            .map(|(name, value)| {
+                let sensitive = SENSITIVE_HEADERS
+                    .iter()
+                    .any(|header| header.eq_ignore_ascii_case(name));
+                let value = if sensitive { REDACTED } else { value };
+                (name.clone(), value.to_string())
+            })
+            .collect();
+        fixtures.push(Fixture {
+            method: method.to_string(),
+            occurrence,
+            url: url.to_string(),
+            status_code: response.status_code,
+            http_version: response.http_version.clone(),
+            tls: response.tls,
+            headers,
+            body: response.body.clone(),
+            binary_body: response
+                .binary_body
+                .as_ref()
+                .map(|body| STANDARD.encode(body)),
+            body_size: response.body_size,
+            dns_ms: response.dns.map(|dns| dns.as_millis() as u64),
+            time_to_headers_ms: response.time_to_headers.as_millis() as u64,
+            time_total_ms: response.time_total.as_millis() as u64,
+        });
+        std::fs::write(&path, serde_json::to_string_pretty(&fixtures)?)
+            .map_err(|err| anyhow!("could not write fixture {}: {}", path.display(), err))
+    }
+}
@@ -0,0 +1,112 @@
+use std::{cell::RefCell, path::Path, time::SystemTime};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::{json, Value};
+
+use super::request::{RequestContext, Response};
+
+fn name_values<'a, T: IntoIterator<Item = (&'a str, &'a str)>>(entries: T) -> Vec<Value> {
+    entries
+        .into_iter()
+        .map(|(name, value)| json!({ "name": name, "value": value }))
+        .collect()
+}
+
+/// response content, binary bodies are base64 encoded
+fn content(response: &Response, mime_type: &str) -> Value {
+    match &response.binary_body {
+        Some(bytes) => json!({
+            "size": bytes.len(),
+            "mimeType": mime_type,
+            "text": STANDARD.encode(bytes),
+            "encoding": "base64",
+        }),
+        None => json!({
+            "size": response.body_size,
+            "mimeType": mime_type,
+            "text": response.body,
+        }),
+    }
+}
+
+/// collects the executed requests as HTTP Archive (HAR 1.2) entries
+#[derive(Default)]
+pub struct HarRecorder {
+    entries: RefCell<Vec<Value>>,
+}
+
+impl HarRecorder {
+    pub fn record(&self, context: &RequestContext, response: &Response) {
+        let started = SystemTime::now() - response.time_total;
+        let request_mime_type = context
+            .headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+            .map(|(_, value)| value.as_str())
+            .unwrap_or("");
+        let response_mime_type = response
+            .headers
+            .get("content-type")
+            .map(String::as_str)
+            .unwrap_or("");
+
+        let mut request = json!({
+            "method": context.method.to_string().to_uppercase(),
+            "url": context.url(),
+            "httpVersion": response.http_version,
+            "cookies": [],
+            "headers": name_values(context.headers.iter().map(|(k, v)| (k.as_str(), v.as_str()))),
+            "queryString": name_values(
+                context.query_params.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+            ),
+            "headersSize": -1,
+            "bodySize": context.body.as_ref().map_or(0, String::len),
+        });
+        if let Some(body) = &context.body {
+            request["postData"] = json!({ "mimeType": request_mime_type, "text": body });
+        }
+
+        let time_total = response.time_total.as_millis();
+        let time_to_headers = response.time_to_headers.as_millis();
+        let entry = json!({
+            "startedDateTime": humantime::format_rfc3339_millis(started).to_string(),
+            "time": time_total,
+            "request": request,
+            "response": {
+                "status": response.status_code,
+                "statusText": reqwest::StatusCode::from_u16(response.status_code)
+                    .ok()
+                    .and_then(|status| status.canonical_reason())
+                    .unwrap_or(""),
+                "httpVersion": response.http_version,
+                "cookies": [],
+                "headers": name_values(response.headers.iter().map(|(k, v)| (k.as_str(), v.as_str()))),
+                "content": content(response, response_mime_type),
+                "redirectURL": response.headers.get("location").map(String::as_str).unwrap_or(""),
+                "headersSize": -1,
+                "bodySize": -1,
+            },
+            "cache": {},
+            "timings": {
+                "dns": response.dns.map_or(-1, |dns| dns.as_millis() as i64),
+                "send": 0,
+                "wait": time_to_headers,
+                "receive": time_total - time_to_headers,
+            },
+        });
+
+        self.entries.borrow_mut().push(entry);
+    }
+
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let har = json!({
+            "log": {
+                "version": "1.2",
+                "creator": { "name": "climan", "version": env!("CARGO_PKG_VERSION") },
+                "entries": *self.entries.borrow(),
+            }
+        });
+        std::fs::write(path, serde_json::to_string_pretty(&har)?)
+            .map_err(|err| anyhow::anyhow!("could not write har file {}: {}", path.display(), err))
+    }
+}
@@ -0,0 +1,593 @@
+use anyhow::anyhow;
+use indexmap::IndexMap;
+use serde::Serialize;
+use serde_json::Value;
+
+use super::model::*;
+use super::request::Request;
+use super::workflow::Workflow;
+
+/// curl flags that take a value but have no equivalent in a request spec
+const IGNORED_VALUE_FLAGS: &[&str] = &[
+    "-o",
+    "--output",
+    "-m",
+    "--max-time",
+    "--connect-timeout",
+    "-x",
+    "--proxy",
+    "--cacert",
+    "--cert",
+    "--key",
+    "-w",
+    "--write-out",
+    "-c",
+    "--cookie-jar",
+    "--retry",
+];
+
+/// curl flags that take a value and are mapped into the request spec
+const VALUE_FLAGS: &[&str] = &[
+    "-X",
+    "--request",
+    "-H",
+    "--header",
+    "-d",
+    "--data",
+    "--data-raw",
+    "--data-binary",
+    "--data-ascii",
+    "--data-urlencode",
+    "--json",
+    "-F",
+    "--form",
+    "-u",
+    "--user",
+    "-A",
+    "--user-agent",
+    "-b",
+    "--cookie",
+    "-e",
+    "--referer",
+    "--url",
+];
+
+/// splits a command line into words following the usual shell quoting rules,
+/// including the `$'...'` quoting used by browsers when copying as curl
+pub(crate) fn shell_words(command: &str) -> anyhow::Result<Vec<String>> {
+    let mut words: Vec<String> = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = command.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(anyhow!("unterminated single quote")),
+                    }
+                }
+            }
+            '$' if chars.peek() == Some(&'\'') => {
+                chars.next();
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => word.push('\n'),
+                            Some('t') => word.push('\t'),
+                            Some('r') => word.push('\r'),
+                            Some(c) => word.push(c),
+                            None => return Err(anyhow!("unterminated quote")),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(anyhow!("unterminated quote")),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some('\n') => {}
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(anyhow!("unterminated double quote")),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(anyhow!("unterminated double quote")),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some('\n') | Some('\r') | None => {}
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+            },
+            c if c.is_whitespace() => {
+                if let Some(word) = word.take() {
+                    words.push(word);
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(word) = word {
+        words.push(word);
+    }
+
+    Ok(words)
+}
+
+/// the options of a command line word, with their value when it is attached:
+/// `--name=value`, `-Xvalue` and combined short flags like `-sI`, where the
+/// last one can take a value as in `-sXPOST`. Other words are returned as they are.
+fn curl_options(word: &str) -> Vec<(String, Option<String>)> {
+    if let Some((flag, value)) = word.split_once('=') {
+        if flag.starts_with("--") {
+            return vec![(flag.to_string(), Some(value.to_string()))];
+        }
+    }
+    if word.len() <= 2 || !word.starts_with('-') || word.starts_with("--") {
+        return vec![(word.to_string(), None)];
+    }
+
+    let mut options = Vec::new();
+    for (index, c) in word.char_indices().skip(1) {
+        let flag = format!("-{c}");
+        let takes_value =
+            VALUE_FLAGS.contains(&flag.as_str()) || IGNORED_VALUE_FLAGS.contains(&flag.as_str());
+        if takes_value {
+            let value = &word[index + c.len_utf8()..];
+            options.push((flag, (!value.is_empty()).then(|| value.to_string())));
+            break;
+        }
+        options.push((flag, None));
+    }
+    options
+}
+
+fn parse_method(method: &str) -> anyhow::Result<Method> {
+    serde_yaml::from_str(method).map_err(|_| anyhow!("unsupported method {method}"))
+}
+
+/// removes null values, so that optional fields which are not set do not
+/// clutter the generated yaml
+fn without_nulls(value: serde_yaml::Value) -> serde_yaml::Value {
+    match value {
+        serde_yaml::Value::Mapping(mapping) => mapping
+            .into_iter()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(key, value)| (key, without_nulls(value)))
+            .collect::<serde_yaml::Mapping>()
+            .into(),
+        serde_yaml::Value::Sequence(sequence) => sequence
+            .into_iter()
+            .map(without_nulls)
+            .collect::<Vec<serde_yaml::Value>>()
+            .into(),
+        value => value,
+    }
+}
+
+/// serializes a spec to yaml, leaving out the optional fields that are not set
+pub fn to_yaml<T: Serialize>(spec: &T) -> anyhow::Result<String> {
+    let value = serde_yaml::to_value(spec)?;
+    Ok(serde_yaml::to_string(&without_nulls(value))?)
+}
+
+/// converts a curl command line into a request spec
+pub fn curl_to_request(command: &str) -> anyhow::Result<Request> {
+    let words = shell_words(command)?;
+    let mut words = words.into_iter().peekable();
+    if words.peek().map(String::as_str) == Some("curl") {
+        words.next();
+    }
+
+    let mut uri: Option<String> = None;
+    let mut method: Option<Method> = None;
+    let mut headers: IndexMap<String, HeaderValue> = IndexMap::new();
+    let mut data: Vec<String> = Vec::new();
+    // `@file` data, curl sends the content of the file
+    let mut data_files: Vec<String> = Vec::new();
+    let mut multipart: Vec<MultipartField> = Vec::new();
+    let mut authentication: Option<Authentication> = None;
+    let mut head = false;
+    let mut get = false;
+
+    while let Some(word) = words.next() {
+        for (flag, attached) in curl_options(&word) {
+            if IGNORED_VALUE_FLAGS.contains(&flag.as_str()) {
+                if attached.is_none() {
+                    words.next();
+                }
+                log::warn!("ignoring curl option {}", flag);
+                continue;
+            }
+
+            if !VALUE_FLAGS.contains(&flag.as_str()) {
+                match flag.as_str() {
+                    "-I" | "--head" => head = true,
+                    "-G" | "--get" => get = true,
+                    flag if flag.starts_with('-') => log::warn!("ignoring curl option {}", flag),
+                    _ => uri = Some(word.clone()),
+                }
+                continue;
+            }
+
+            let value = match attached {
+                Some(value) => value,
+                None => words
+                    .next()
+                    .ok_or_else(|| anyhow!("missing value for curl option {}", flag))?,
+            };
+
+            match flag.as_str() {
+                "-X" | "--request" => method = Some(parse_method(&value)?),
+                "-H" | "--header" => {
+                    let (name, header_value) = value
+                        .split_once(':')
+                        .ok_or_else(|| anyhow!("invalid header {}", value))?;
+                    let header_value = header_value.trim().to_string();
+                    match headers.get_mut(name.trim()) {
+                        Some(values) => values.push(header_value),
+                        None => {
+                            headers.insert(name.trim().to_string(), header_value.into());
+                        }
+                    }
+                }
+                "-F" | "--form" => {
+                    let (name, field_value) = value
+                        .split_once('=')
+                        .ok_or_else(|| anyhow!("invalid form field {}", value))?;
+                    let field = match field_value.strip_prefix('@') {
+                        Some(file) => MultipartField::File {
+                            name: name.to_string(),
+                            file: file.to_string(),
+                            filename: None,
+                            content_type: None,
+                        },
+                        None => MultipartField::Text {
+                            name: name.to_string(),
+                            value: field_value.to_string(),
+                        },
+                    };
+                    multipart.push(field);
+                }
+                "-u" | "--user" => {
+                    let (username, password) = match value.split_once(':') {
+                        Some((username, password)) => (username, Some(password.to_string())),
+                        None => (value.as_str(), None),
+                    };
+                    authentication = Some(Authentication::Basic {
+                        username: username.to_string(),
+                        password,
+                    });
+                }
+                "-A" | "--user-agent" => {
+                    headers.insert("User-Agent".to_string(), value.into());
+                }
+                "-b" | "--cookie" => {
+                    headers.insert("Cookie".to_string(), value.into());
+                }
+                "-e" | "--referer" => {
+                    headers.insert("Referer".to_string(), value.into());
+                }
+                "--url" => uri = Some(value),
+                "--data-raw" => data.push(value),
+                "--data-urlencode" => data.push(curl_urlencode(&value)),
+                _ => {
+                    if flag == "--json" {
+                        headers
+                            .entry("Content-Type".to_string())
+                            .or_insert_with(|| "application/json".to_string().into());
+                        headers
+                            .entry("Accept".to_string())
+                            .or_insert_with(|| "application/json".to_string().into());
+                    }
+                    // unlike --data-raw, the other data options read `@file` values from the file
+                    match value.strip_prefix('@') {
+                        Some(file) => data_files.push(file.to_string()),
+                        None => data.push(value),
+                    }
+                }
+            }
+        }
+    }
+
+    let mut uri = uri.ok_or_else(|| anyhow!("no url found in curl command"))?;
+    let data = data.join("&");
+
+    let body = if !multipart.is_empty() {
+        Some(Body::Multipart { multipart })
+    } else if let Some(file) = data_files.first() {
+        // the content of the file is not read, so it can only be the whole body
+        if data_files.len() > 1 || !data.is_empty() || get || file == "-" {
+            return Err(anyhow!(
+                "curl data @{} can only be imported as the only data of a request",
+                file
+            ));
+        }
+        Some(Body::File { file: file.clone() })
+    } else if get || data.is_empty() {
+        None
+    } else {
+        Some(Body::Content {
+            content: data.clone(),
+            trim: None,
+        })
+    };
+
+    if get && !data.is_empty() {
+        let separator = if uri.contains('?') { '&' } else { '?' };
+        uri = format!("{uri}{separator}{data}");
+    }
+
+    let method = match method {
+        Some(method) => method,
+        None if head => Method::Head,
+        None if body.is_some() => Method::Post,
+        None => Method::Get,
+    };
+
+    let name = reqwest::Url::parse(&uri)
+        .map(|url| format!("{} {}", method, url.path()))
+        .unwrap_or_else(|_| format!("{} {}", method, uri));
+
+    Ok(Request {
+        name,
+        uri,
+        method,
+        headers: if headers.is_empty() {
+            None
+        } else {
+            Some(headers)
+        },
+        body,
+        authentication,
+        ..Default::default()
+    })
+}
+
+/// encodes a `--data-urlencode` value like curl: `content` and `=content` are
+/// encoded as a whole, `name=content` only encodes the content. The `@file`
+/// forms are kept as they are since the file is not read.
+fn curl_urlencode(value: &str) -> String {
+    if value.contains('@') && !value.contains('=') {
+        return value.to_string();
+    }
+    match value.split_once('=') {
+        Some(("", content)) => percent_encode(content),
+        Some((name, content)) => format!("{name}={}", percent_encode(content)),
+        None => percent_encode(value),
+    }
+}
+
+/// percent-encodes everything but the unreserved characters, as curl does
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(byte).to_string()
+            }
+            byte => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+/// translates postman `{{var}}` placeholders to the `{{ var }}` template syntax
+fn postman_template(value: &str) -> String {
+    let placeholder = regex::Regex::new(r"\{\{\s*([^{}\s]+)\s*\}\}").unwrap();
+    placeholder.replace_all(value, "{{ $1 }}").to_string()
+}
+
+fn postman_string(value: &Value, key: &str) -> Option<String> {
+    value.get(key).and_then(Value::as_str).map(postman_template)
+}
+
+/// key/value lists (headers, url encoded forms, ...) without the disabled entries
+fn postman_entries(value: Option<&Value>) -> impl Iterator<Item = &Value> {
+    value
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter(|entry| {
+            !entry
+                .get("disabled")
+                .and_then(Value::as_bool)
+                .unwrap_or(false)
+        })
+}
+
+/// looks up the value of an auth attribute, e.g. the `token` of a bearer auth
+fn postman_auth_value(auth: &Value, auth_type: &str, key: &str) -> Option<String> {
+    postman_entries(auth.get(auth_type))
+        .find(|entry| entry.get("key").and_then(Value::as_str) == Some(key))
+        .and_then(|entry| postman_string(entry, "value"))
+}
+
+fn postman_authentication(auth: &Value) -> Option<Authentication> {
+    match auth.get("type").and_then(Value::as_str) {
+        Some("bearer") => Some(Authentication::Bearer {
+            token: postman_auth_value(auth, "bearer", "token")?,
+        }),
+        Some("basic") => Some(Authentication::Basic {
+            username: postman_auth_value(auth, "basic", "username")?,
+            password: postman_auth_value(auth, "basic", "password"),
+        }),
+        Some("apikey") => Some(Authentication::ApiKey {
+            key: postman_auth_value(auth, "apikey", "key")?,
+            value: postman_auth_value(auth, "apikey", "value").unwrap_or_default(),
+            location: match postman_auth_value(auth, "apikey", "in").as_deref() {
+                Some("query") => Some(ApiKeyLocation::Query),
+                _ => None,
+            },
+        }),
+        Some("noauth") | None => None,
+        Some(auth_type) => {
+            log::warn!("ignoring unsupported postman auth type {}", auth_type);
+            None
+        }
+    }
+}
+
+fn postman_body(body: &Value) -> Option<Body> {
+    match body.get("mode").and_then(Value::as_str) {
+        Some("raw") => Some(Body::Content {
+            content: postman_string(body, "raw")?,
+            trim: None,
+        }),
+        Some("urlencoded") => Some(Body::Form {
+            form: postman_entries(body.get("urlencoded"))
+                .filter_map(|entry| {
+                    Some((
+                        postman_string(entry, "key")?,
+                        postman_string(entry, "value").unwrap_or_default(),
+                    ))
+                })
+                .collect(),
+        }),
+        Some("formdata") => Some(Body::Multipart {
+            multipart: postman_entries(body.get("formdata"))
+                .filter_map(|entry| {
+                    let name = postman_string(entry, "key")?;
+                    Some(match entry.get("type").and_then(Value::as_str) {
+                        Some("file") => MultipartField::File {
+                            name,
+                            file: postman_string(entry, "src")?,
+                            filename: None,
+                            content_type: postman_string(entry, "contentType"),
+                        },
+                        _ => MultipartField::Text {
+                            name,
+                            value: postman_string(entry, "value").unwrap_or_default(),
+                        },
+                    })
+                })
+                .collect(),
+        }),
+        Some("graphql") => {
+            let graphql = body.get("graphql")?;
+            Some(Body::GraphQl {
+                query: postman_string(graphql, "query")?,
+                variables: graphql
+                    .get("variables")
+                    .and_then(Value::as_str)
+                    .filter(|variables| !variables.trim().is_empty())
+                    .and_then(|variables| serde_json::from_str(variables).ok()),
+                operation_name: None,
+            })
+        }
+        Some(mode) => {
+            log::warn!("ignoring unsupported postman body mode {}", mode);
+            None
+        }
+        None => None,
+    }
+}
+
+fn postman_request(
+    name: String,
+    request: &Value,
+    inherited_auth: Option<&Value>,
+) -> anyhow::Result<Request> {
+    let uri = match request.get("url") {
+        Some(Value::String(url)) => postman_template(url),
+        Some(url) => {
+            postman_string(url, "raw").ok_or_else(|| anyhow!("request {} has no raw url", name))?
+        }
+        None => return Err(anyhow!("request {} has no url", name)),
+    };
+    let method = parse_method(
+        request
+            .get("method")
+            .and_then(Value::as_str)
+            .unwrap_or("GET"),
+    )?;
+
+    let headers: IndexMap<String, HeaderValue> = postman_entries(request.get("header"))
+        .filter_map(|header| {
+            Some((
+                postman_string(header, "key")?,
+                postman_string(header, "value").unwrap_or_default().into(),
+            ))
+        })
+        .collect();
+
+    Ok(Request {
+        name,
+        uri,
+        method,
+        headers: if headers.is_empty() {
+            None
+        } else {
+            Some(headers)
+        },
+        body: request.get("body").and_then(postman_body),
+        authentication: request
+            .get("auth")
+            .or(inherited_auth)
+            .and_then(postman_authentication),
+        ..Default::default()
+    })
+}
+
+/// flattens the (possibly nested) postman folders into a list of requests,
+/// requests inside folders are named `folder / request`
+fn postman_items(
+    items: &Value,
+    prefix: Option<&str>,
+    inherited_auth: Option<&Value>,
+    requests: &mut Vec<Request>,
+) -> anyhow::Result<()> {
+    for item in items.as_array().into_iter().flatten() {
+        let item_name = item
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or("request");
+        let name = match prefix {
+            Some(prefix) => format!("{prefix} / {item_name}"),
+            None => item_name.to_string(),
+        };
+        let auth = item.get("auth").or(inherited_auth);
+
+        if let Some(children) = item.get("item") {
+            postman_items(children, Some(&name), auth, requests)?;
+        } else if let Some(request) = item.get("request") {
+            requests.push(postman_request(name, request, auth)?);
+        }
+    }
+    Ok(())
+}
+
+/// converts a postman v2.1 collection into a workflow spec
+pub fn postman_to_workflow(collection: &str) -> anyhow::Result<Workflow> {
+    let collection: Value = serde_json::from_str(collection)?;
+    let name = collection
+        .get("info")
+        .and_then(|info| info.get("name"))
+        .and_then(Value::as_str)
+        .unwrap_or("postman collection")
+        .to_string();
+
+    let mut requests: Vec<Request> = Vec::new();
+    postman_items(
+        collection
+            .get("item")
+            .ok_or_else(|| anyhow!("collection has no items"))?,
+        None,
+        collection.get("auth"),
+        &mut requests,
+    )?;
+
+    Ok(Workflow::new(name, requests))
+}
@@ -0,0 +1,51 @@
+use tokio::sync::watch;
+
+/// tells whether Ctrl-C was pressed. The handler is installed before the
+/// requests are sent so that the signal is never lost, a second Ctrl-C exits
+/// at once. It is removed once every clone is dropped.
+#[derive(Debug, Clone)]
+pub struct Interrupt(watch::Receiver<bool>);
+
+/// exit code of runs stopped with Ctrl-C, like shells report SIGINT
+pub const EXIT_INTERRUPTED: u8 = 130;
+
+impl Interrupt {
+    pub fn install() -> Interrupt {
+        let (sender, receiver) = watch::channel(false);
+        tokio::spawn(async move {
+            let signal = tokio::select! {
+                signal = tokio::signal::ctrl_c() => signal,
+                () = sender.closed() => return,
+            };
+            if signal.is_err() {
+                return;
+            }
+            log::warn!("stopping, press Ctrl-C again to exit immediately");
+            let _ = sender.send(true);
+            tokio::select! {
+                Ok(()) = tokio::signal::ctrl_c() => std::process::exit(EXIT_INTERRUPTED.into()),
+                () = sender.closed() => {}
+            }
+        });
+        Interrupt(receiver)
+    }
+
+    pub fn is_set(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// completes once Ctrl-C is pressed
+    pub async fn wait(&self) {
+        let mut receiver = self.0.clone();
+        // the sender only goes away when the signal can not be watched
+        if receiver.wait_for(|interrupted| *interrupted).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+impl From<watch::Receiver<bool>> for Interrupt {
+    fn from(receiver: watch::Receiver<bool>) -> Interrupt {
+        Interrupt(receiver)
+    }
+}
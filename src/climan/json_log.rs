@@ -0,0 +1,91 @@
+use std::{
+    io::Write,
+    sync::{Mutex, PoisonError},
+};
+
+use log::{
+    kv::{self, VisitSource},
+    LevelFilter, Log, Metadata, Record,
+};
+use serde_json::{Map, Value};
+
+/// logger writing a json object per line with the time, level, target and
+/// message of each entry, plus its key-values like `request` and `step`
+pub struct JsonLogger {
+    level: LevelFilter,
+    config: simplelog::Config,
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl JsonLogger {
+    pub fn new(level: LevelFilter, writer: Box<dyn Write + Send>) -> Box<JsonLogger> {
+        Box::new(JsonLogger {
+            level,
+            config: simplelog::Config::default(),
+            writer: Mutex::new(writer),
+        })
+    }
+}
+
+/// collects the key-values of a record as json fields
+struct Fields<'a>(&'a mut Map<String, Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(value) = value.to_u64() {
+            Value::from(value)
+        } else if let Some(value) = value.to_i64() {
+            Value::from(value)
+        } else if let Some(value) = value.to_bool() {
+            Value::from(value)
+        } else {
+            Value::from(value.to_string())
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut entry = Map::new();
+        entry.insert("time".into(), chrono::Utc::now().to_rfc3339().into());
+        entry.insert("level".into(), record.level().as_str().into());
+        entry.insert("target".into(), record.target().into());
+        entry.insert("message".into(), record.args().to_string().into());
+        // entries are still written when a key-value can not be read
+        let _ = record.key_values().visit(&mut Fields(&mut entry));
+
+        let mut writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        let _ = writeln!(writer, "{}", Value::Object(entry));
+    }
+
+    fn flush(&self) {
+        let _ = self
+            .writer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .flush();
+    }
+}
+
+impl simplelog::SharedLogger for JsonLogger {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&simplelog::Config> {
+        Some(&self.config)
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        Box::new(*self)
+    }
+}
@@ -0,0 +1,110 @@
+use std::{cell::RefCell, fmt::Write, path::Path, time::Duration};
+
+#[derive(Debug)]
+enum Outcome {
+    Passed,
+    /// the response did not match the expected status or assertions
+    Failed(String),
+    /// no response was received, e.g. the request timed out
+    Error(String),
+    /// the `when` condition was false
+    Skipped,
+}
+
+#[derive(Debug)]
+struct TestCase {
+    workflow: String,
+    name: String,
+    time: Duration,
+    outcome: Outcome,
+}
+
+/// collects the outcome of the workflow steps as JUnit test cases, steps
+/// repeated with `forEach` yield one test case per iteration
+#[derive(Debug, Default)]
+pub struct JunitReport {
+    cases: RefCell<Vec<TestCase>>,
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl JunitReport {
+    fn push(&self, workflow: &str, name: &str, time: Duration, outcome: Outcome) {
+        self.cases.borrow_mut().push(TestCase {
+            workflow: workflow.to_string(),
+            name: name.to_string(),
+            time,
+            outcome,
+        });
+    }
+
+    pub fn passed(&self, workflow: &str, name: &str, time: Duration) {
+        self.push(workflow, name, time, Outcome::Passed);
+    }
+
+    pub fn failed(&self, workflow: &str, name: &str, time: Duration, message: String) {
+        self.push(workflow, name, time, Outcome::Failed(message));
+    }
+
+    pub fn error(&self, workflow: &str, name: &str, message: String) {
+        self.push(workflow, name, Duration::ZERO, Outcome::Error(message));
+    }
+
+    pub fn skipped(&self, workflow: &str, name: &str) {
+        self.push(workflow, name, Duration::ZERO, Outcome::Skipped);
+    }
+
+    /// writes the report as a single test suite named after the workflow
+    pub fn write(&self, path: &Path, suite: &str) -> anyhow::Result<()> {
+        let cases = self.cases.borrow();
+        let count = |matches: fn(&Outcome) -> bool| {
+            cases.iter().filter(|case| matches(&case.outcome)).count()
+        };
+        let time: Duration = cases.iter().map(|case| case.time).sum();
+
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n");
+        writeln!(
+            xml,
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{:.3}\">",
+            escape(suite),
+            cases.len(),
+            count(|outcome| matches!(outcome, Outcome::Failed(_))),
+            count(|outcome| matches!(outcome, Outcome::Error(_))),
+            count(|outcome| matches!(outcome, Outcome::Skipped)),
+            time.as_secs_f64()
+        )?;
+        for case in cases.iter() {
+            write!(
+                xml,
+                "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+                escape(&case.name),
+                escape(&case.workflow),
+                case.time.as_secs_f64()
+            )?;
+            match &case.outcome {
+                Outcome::Passed => xml.push_str("/>\n"),
+                Outcome::Failed(message) => writeln!(
+                    xml,
+                    ">\n      <failure message=\"{0}\">{0}</failure>\n    </testcase>",
+                    escape(message)
+                )?,
+                Outcome::Error(message) => writeln!(
+                    xml,
+                    ">\n      <error message=\"{0}\">{0}</error>\n    </testcase>",
+                    escape(message)
+                )?,
+                Outcome::Skipped => xml.push_str(">\n      <skipped/>\n    </testcase>\n"),
+            }
+        }
+        xml.push_str("  </testsuite>\n</testsuites>\n");
+
+        std::fs::write(path, xml).map_err(|err| {
+            anyhow::anyhow!("could not write junit report {}: {}", path.display(), err)
+        })
+    }
+}
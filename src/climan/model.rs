@@ -1,8 +1,15 @@
+use std::{collections::HashMap, fmt, path::Path, time::Duration};
+
+use anyhow::anyhow;
+use indexmap::IndexMap;
 use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use super::template::Templates;
 
-#[derive(Serialize, Deserialize, Debug, JsonSchema, strum::Display, Clone)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema, strum::Display, Clone, Default)]
 pub enum Method {
+    #[default]
     #[serde(alias = "get")]
     #[serde(alias = "GET")]
     Get,
@@ -21,6 +28,15 @@ pub enum Method {
     #[serde(alias = "head")]
     #[serde(alias = "HEAD")]
     Head,
+    #[serde(alias = "options")]
+    #[serde(alias = "OPTIONS")]
+    Options,
+    #[serde(alias = "trace")]
+    #[serde(alias = "TRACE")]
+    Trace,
+    #[serde(alias = "connect")]
+    #[serde(alias = "CONNECT")]
+    Connect,
 }
 
 #[derive(Serialize, Deserialize, Debug, JsonSchema, Clone)]
@@ -33,17 +49,146 @@ pub enum ParamValue {
     ListParam(Vec<serde_json::Value>),
 }
 
+/// a header value, lists send the header once per value. Numbers and booleans
+/// are sent as written.
 #[derive(Serialize, Deserialize, Debug, JsonSchema, Clone)]
 #[serde(untagged)]
+pub enum HeaderValue {
+    #[serde(deserialize_with = "scalar_string")]
+    Single(String),
+    #[serde(deserialize_with = "scalar_strings")]
+    List(Vec<String>),
+}
+
+fn scalar_to_string<E: serde::de::Error>(value: serde_json::Value) -> Result<String, E> {
+    match value {
+        serde_json::Value::String(value) => Ok(value),
+        serde_json::Value::Number(value) => Ok(value.to_string()),
+        serde_json::Value::Bool(value) => Ok(value.to_string()),
+        value => Err(E::custom(format!(
+            "header values must be strings, numbers or booleans, got {}",
+            value
+        ))),
+    }
+}
+
+fn scalar_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    scalar_to_string(serde_json::Value::deserialize(deserializer)?)
+}
+
+fn scalar_strings<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    Vec::<serde_json::Value>::deserialize(deserializer)?
+        .into_iter()
+        .map(scalar_to_string)
+        .collect()
+}
+
+impl HeaderValue {
+    pub fn values(&self) -> &[String] {
+        match self {
+            HeaderValue::Single(value) => std::slice::from_ref(value),
+            HeaderValue::List(values) => values,
+        }
+    }
+
+    /// adds a value, turning a single value into a list
+    pub fn push(&mut self, value: String) {
+        match self {
+            HeaderValue::Single(first) => {
+                *self = HeaderValue::List(vec![std::mem::take(first), value])
+            }
+            HeaderValue::List(values) => values.push(value),
+        }
+    }
+}
+
+impl From<String> for HeaderValue {
+    fn from(value: String) -> Self {
+        HeaderValue::Single(value)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema, Clone)]
+#[serde(
+    untagged,
+    deny_unknown_fields,
+    expecting = "a body with only the fields of one of `file`, `content`, `form`, `multipart`, `query`, `json`, `base`, `var` or `items`"
+)]
 pub enum Body {
-    File { file: String },
-    Content { content: String, trim: Option<bool> },
+    File {
+        file: String,
+    },
+    Content {
+        content: String,
+        trim: Option<bool>,
+    },
+    Form {
+        form: IndexMap<String, String>,
+    },
+    Multipart {
+        multipart: Vec<MultipartField>,
+    },
+    GraphQl {
+        query: String,
+        /// its strings are templates, like the ones of `json` bodies
+        variables: Option<serde_json::Value>,
+        #[serde(rename = "operationName")]
+        operation_name: Option<String>,
+    },
+    /// a json document written as yaml, its strings are templates
+    Json {
+        json: serde_json::Value,
+    },
+    /// the json held by the `base` variable with `patch` merged into it as a
+    /// json merge patch: objects are merged, `null` removes a field. Dry runs
+    /// show the patch alone.
+    JsonMerge {
+        base: String,
+        patch: serde_json::Value,
+    },
+    /// the value of a variable as it is, without rendering it
+    Variable {
+        var: String,
+    },
+    /// json values sent one per line as newline delimited json
+    Ndjson {
+        items: Vec<serde_json::Value>,
+    },
 }
 
-impl Body {
-    pub fn content(&self) -> Vec<u8> {
+#[derive(Serialize, Deserialize, Debug, JsonSchema, Clone)]
+#[serde(untagged)]
+pub enum MultipartField {
+    File {
+        name: String,
+        file: String,
+        filename: Option<String>,
+        #[serde(rename = "contentType")]
+        content_type: Option<String>,
+    },
+    Text {
+        name: String,
+        value: String,
+    },
+}
+
+impl MultipartField {
+    /// curl-like `name=value` / `name=@file` representation used for previews
+    pub fn summary(&self) -> String {
         match self {
-            Body::File { file } => std::fs::read(file).unwrap(),
+            MultipartField::File { name, file, .. } => format!("{name}=@{file}"),
+            MultipartField::Text { name, value } => format!("{name}={value}"),
+        }
+    }
+}
+
+impl Body {
+    /// the body as sent, relative files are read from `base_dir`. Bodies built
+    /// from the variables are only available from the rendered request.
+    pub fn content(&self, base_dir: &Path) -> anyhow::Result<Vec<u8>> {
+        let content = match self {
+            Body::File { file } => std::fs::read(base_dir.join(file))
+                .map_err(|err| anyhow!("could not read body file {}: {}", file, err))?,
             Body::Content { content, trim } => {
                 let value = if trim.unwrap_or(false) {
                     content.trim()
@@ -52,19 +197,376 @@ impl Body {
                 };
                 value.as_bytes().to_vec()
             }
+            Body::Form { form } => serde_urlencoded::to_string(form)
+                .map_err(|err| anyhow!("could not encode form body: {}", err))?
+                .into_bytes(),
+            Body::Multipart { multipart } => multipart
+                .iter()
+                .map(MultipartField::summary)
+                .collect::<Vec<String>>()
+                .join("\n")
+                .into_bytes(),
+            Body::GraphQl {
+                query,
+                variables,
+                operation_name,
+            } => graphql_payload(query, variables, operation_name)
+                .to_string()
+                .into_bytes(),
+            // these need the variables, the request builds them
+            Body::Json { .. } => return Err(anyhow!("json bodies are built with the request")),
+            Body::JsonMerge { .. } => {
+                return Err(anyhow!("jsonMerge bodies are built with the request"))
+            }
+            Body::Variable { .. } => return Err(anyhow!("var bodies are built with the request")),
+            Body::Ndjson { .. } => return Err(anyhow!("items bodies are built with the request")),
+        };
+        Ok(content)
+    }
+}
+
+/// the values serialized on a line each, every line ends with a newline
+pub fn ndjson<'a>(items: impl IntoIterator<Item = &'a serde_json::Value>) -> String {
+    items.into_iter().map(|item| format!("{item}\n")).collect()
+}
+
+/// applies a json merge patch (RFC 7396) to `target`
+pub fn merge_json(target: &mut serde_json::Value, patch: &serde_json::Value) {
+    let serde_json::Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = serde_json::Value::Object(Default::default());
+    }
+    if let serde_json::Value::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(key);
+            } else {
+                merge_json(
+                    target.entry(key.clone()).or_insert(serde_json::Value::Null),
+                    value,
+                );
+            }
         }
     }
 }
 
+/// builds the standard `{"query", "variables", "operationName"}` GraphQL envelope
+pub fn graphql_payload(
+    query: &str,
+    variables: &Option<serde_json::Value>,
+    operation_name: &Option<String>,
+) -> serde_json::Value {
+    let mut payload = serde_json::json!({ "query": query });
+    if let Some(variables) = variables {
+        payload["variables"] = variables.clone();
+    }
+    if let Some(operation_name) = operation_name {
+        payload["operationName"] = operation_name.clone().into();
+    }
+    payload
+}
+
 #[derive(Serialize, Deserialize, Debug, JsonSchema, Clone)]
-#[serde(tag = "type")]
+#[serde(tag = "type", deny_unknown_fields)]
 pub enum Authentication {
+    /// `password` can be read from a file with the `file:` prefix
     #[serde(rename = "basic")]
     Basic {
         username: String,
         password: Option<String>,
     },
 
+    /// `token` can be read from a file with the `file:` prefix
     #[serde(rename = "bearer")]
     Bearer { token: String },
+
+    /// api key sent as a header or query parameter, only the value can reference variables
+    #[serde(rename = "apiKey")]
+    ApiKey {
+        key: String,
+        value: String,
+        /// where the key is sent (default: header)
+        location: Option<ApiKeyLocation>,
+    },
+
+    /// OAuth2 client credentials grant, the token is fetched once and reused
+    /// by all the requests of the run until it expires
+    #[serde(rename = "oauth2")]
+    OAuth2 {
+        #[serde(rename = "tokenUrl")]
+        token_url: String,
+        #[serde(rename = "clientId")]
+        client_id: String,
+        #[serde(rename = "clientSecret")]
+        client_secret: String,
+        scope: Option<String>,
+    },
+
+    /// AWS Signature Version 4, the request is signed once fully built
+    #[serde(rename = "awsSigV4")]
+    AwsSigV4 {
+        #[serde(rename = "accessKey")]
+        access_key: String,
+        #[serde(rename = "secretKey")]
+        secret_key: String,
+        region: String,
+        service: String,
+        /// needed for temporary credentials
+        #[serde(rename = "sessionToken")]
+        session_token: Option<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema, Clone, Copy, PartialEq, Default)]
+pub enum ApiKeyLocation {
+    #[default]
+    #[serde(rename = "header")]
+    Header,
+    #[serde(rename = "query")]
+    Query,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema, Clone)]
+#[serde(untagged)]
+pub enum DurationValue {
+    /// duration in seconds
+    Seconds(u64),
+    /// humantime duration, e.g. "30s" or "1m 30s"
+    Text(String),
+}
+
+impl DurationValue {
+    pub fn duration(&self) -> anyhow::Result<Duration> {
+        match self {
+            DurationValue::Seconds(seconds) => Ok(Duration::from_secs(*seconds)),
+            DurationValue::Text(text) => humantime::parse_duration(text)
+                .map_err(|err| anyhow!("invalid duration `{text}`: {err}")),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema, Clone)]
+pub struct Retry {
+    /// total number of attempts, including the first one
+    #[serde(rename = "maxAttempts")]
+    pub max_attempts: u32,
+    /// delay before the first retry, doubled after each attempt (default: 500).
+    /// The `Retry-After` header of a retried response takes precedence.
+    #[serde(rename = "backoffMs")]
+    pub backoff_ms: Option<u64>,
+    /// status codes that trigger a retry (default: 429 and any 5xx)
+    #[serde(rename = "retryOnStatus")]
+    pub retry_on_status: Option<Vec<u16>>,
+}
+
+impl Retry {
+    pub fn retries_status(&self, status_code: u16) -> bool {
+        match &self.retry_on_status {
+            Some(statuses) => statuses.contains(&status_code),
+            None => status_code == 429 || (500..=599).contains(&status_code),
+        }
+    }
+}
+
+/// status codes considered successful: a code, a list of codes or a class
+/// like `2xx`
+#[derive(Serialize, Deserialize, Debug, JsonSchema, Clone)]
+#[serde(
+    untagged,
+    expecting = "a status code, a list of status codes or a class like `2xx`"
+)]
+pub enum ExpectedStatus {
+    Single(u16),
+    List(Vec<u16>),
+    Class(#[schemars(with = "String")] StatusClass),
+}
+
+impl ExpectedStatus {
+    pub fn matches(&self, status_code: u16) -> bool {
+        match self {
+            ExpectedStatus::Single(expected) => *expected == status_code,
+            ExpectedStatus::List(expected) => expected.contains(&status_code),
+            ExpectedStatus::Class(class) => status_code / 100 == class.0,
+        }
+    }
+}
+
+/// whether the status is one of the expected ones, any 2xx status is when
+/// none are given
+pub fn is_expected_status(expected: Option<&ExpectedStatus>, status_code: u16) -> bool {
+    match expected {
+        Some(expected) => expected.matches(status_code),
+        None => (200..300).contains(&status_code),
+    }
+}
+
+impl fmt::Display for ExpectedStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExpectedStatus::Single(expected) => write!(f, "{expected}"),
+            ExpectedStatus::List(expected) => {
+                let codes: Vec<String> = expected.iter().map(u16::to_string).collect();
+                write!(f, "one of [{}]", codes.join(", "))
+            }
+            ExpectedStatus::Class(class) => write!(f, "{}", String::from(*class)),
+        }
+    }
+}
+
+/// the status codes with the same first digit, written like `2xx`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(try_from = "String", into = "String")]
+pub struct StatusClass(u16);
+
+impl TryFrom<String> for StatusClass {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.to_lowercase().as_bytes() {
+            [digit @ b'1'..=b'5', b'x', b'x'] => Ok(StatusClass(u16::from(digit - b'0'))),
+            _ => Err(format!(
+                "invalid status class `{value}`, expected one like `2xx`"
+            )),
+        }
+    }
+}
+
+impl From<StatusClass> for String {
+    fn from(class: StatusClass) -> String {
+        format!("{}xx", class.0)
+    }
+}
+
+/// a check performed on the response, string values can reference variables
+#[derive(Serialize, Deserialize, Debug, JsonSchema, Clone)]
+#[serde(untagged)]
+pub enum Assertion {
+    Status {
+        status: ExpectedStatus,
+    },
+    Header {
+        header: String,
+        equals: String,
+    },
+    BodyContains {
+        #[serde(rename = "bodyContains")]
+        body_contains: String,
+    },
+    JsonPath {
+        jsonpath: String,
+        equals: serde_json::Value,
+    },
+}
+
+/// values a request is repeated for, either inline or the name of a variable
+/// holding a json list
+#[derive(Serialize, Deserialize, Debug, JsonSchema, Clone)]
+#[serde(untagged)]
+pub enum ForEach {
+    List(Vec<serde_json::Value>),
+    Variable(String),
+}
+
+impl ForEach {
+    /// the loop values as they are exposed to templates, strings without quotes
+    pub fn items(
+        &self,
+        variables: &HashMap<String, Option<String>>,
+    ) -> anyhow::Result<Vec<String>> {
+        let values = match self {
+            ForEach::List(values) => values.clone(),
+            ForEach::Variable(name) => {
+                let value = variables
+                    .get(name)
+                    .cloned()
+                    .flatten()
+                    .ok_or_else(|| anyhow!("forEach variable `{name}` is not set"))?;
+                serde_json::from_str(&value)
+                    .map_err(|err| anyhow!("forEach variable `{name}` is not a json list: {err}"))?
+            }
+        };
+
+        Ok(values
+            .into_iter()
+            .map(|value| match value {
+                serde_json::Value::String(value) => value,
+                value => value.to_string(),
+            })
+            .collect())
+    }
+}
+
+/// repeats a request for each page of a paginated response. The value yielded
+/// by `next` is available to the following request as the `cursor` variable,
+/// the bodies of all the pages are exposed as the `pages` json list.
+#[derive(Serialize, Deserialize, Debug, JsonSchema, Clone)]
+pub struct Paginate {
+    /// extractor yielding the next page url or cursor, `header:<name>` reads a
+    /// response header. Pagination stops when it yields nothing.
+    pub next: String,
+    /// expression evaluated after each page, pagination stops when false
+    #[serde(rename = "while")]
+    pub condition: Option<String>,
+    /// maximum number of pages fetched (default: 100)
+    #[serde(rename = "maxPages")]
+    pub max_pages: Option<usize>,
+}
+
+/// redirect policy for the `followRedirects` and `maxRedirects` settings,
+/// redirects are followed up to reqwest's default limit when nothing is set
+pub fn redirect_policy(
+    follow_redirects: Option<bool>,
+    max_redirects: Option<usize>,
+) -> reqwest::redirect::Policy {
+    match (follow_redirects.unwrap_or(true), max_redirects) {
+        (false, _) => reqwest::redirect::Policy::none(),
+        (true, Some(max_redirects)) => reqwest::redirect::Policy::limited(max_redirects),
+        (true, None) => reqwest::redirect::Policy::default(),
+    }
+}
+
+/// client certificate used for mutual TLS, paths can reference variables
+#[derive(Serialize, Deserialize, Debug, JsonSchema, Clone)]
+#[serde(untagged)]
+pub enum ClientCertificate {
+    /// PEM encoded certificate (chain) and PKCS#8 private key
+    Pem { cert: String, key: String },
+    /// PKCS#12 archive
+    Pkcs12 {
+        pkcs12: String,
+        password: Option<String>,
+    },
+}
+
+impl ClientCertificate {
+    pub fn identity(
+        &self,
+        templates: &Templates,
+        variables: &HashMap<String, Option<String>>,
+    ) -> anyhow::Result<reqwest::Identity> {
+        let read = |path: &str| {
+            let path = templates.render(path, variables)?;
+            std::fs::read(&path)
+                .map_err(|err| anyhow!("could not read client certificate file {}: {}", path, err))
+        };
+
+        match self {
+            ClientCertificate::Pem { cert, key } => {
+                reqwest::Identity::from_pkcs8_pem(&read(cert)?, &read(key)?)
+                    .map_err(|err| anyhow!("could not load PEM client certificate: {}", err))
+            }
+            ClientCertificate::Pkcs12 { pkcs12, password } => {
+                let password = password
+                    .as_deref()
+                    .map(|password| templates.render(password, variables))
+                    .transpose()?
+                    .unwrap_or_default();
+                reqwest::Identity::from_pkcs12_der(&read(pkcs12)?, &password)
+                    .map_err(|err| anyhow!("could not load PKCS#12 client certificate: {}", err))
+            }
+        }
+    }
 }
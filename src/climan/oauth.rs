@@ -0,0 +1,119 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use reqwest::Client;
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+/// tokens are refreshed this long before they expire
+const EXPIRY_MARGIN: Duration = Duration::from_secs(10);
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+}
+
+struct CachedToken {
+    access_token: String,
+    expires_at: Option<Instant>,
+}
+
+/// token url, client id, client secret and scope
+type TokenKey = (String, String, String, String);
+
+/// tokens fetched during a run, shared by its requests. Requests needing the
+/// same token wait for the one fetching it instead of fetching it again.
+#[derive(Default)]
+pub struct TokenCache {
+    tokens: RefCell<HashMap<TokenKey, Rc<Mutex<Option<CachedToken>>>>>,
+}
+
+impl fmt::Debug for TokenCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenCache").finish_non_exhaustive()
+    }
+}
+
+impl TokenCache {
+    /// returns an access token obtained with the client credentials grant,
+    /// tokens are reused until they expire
+    pub async fn client_credentials_token(
+        &self,
+        client: &Client,
+        token_url: &str,
+        client_id: &str,
+        client_secret: &str,
+        scope: Option<&str>,
+    ) -> anyhow::Result<String> {
+        let cache_key = (
+            token_url.to_string(),
+            client_id.to_string(),
+            client_secret.to_string(),
+            scope.unwrap_or_default().to_string(),
+        );
+        let slot = self
+            .tokens
+            .borrow_mut()
+            .entry(cache_key)
+            .or_default()
+            .clone();
+        let mut cached = slot.lock().await;
+        if let Some(token) = cached.as_ref().filter(
+            |token| !matches!(token.expires_at, Some(expires_at) if Instant::now() >= expires_at),
+        ) {
+            return Ok(token.access_token.clone());
+        }
+
+        let token = fetch_token(client, token_url, client_id, client_secret, scope).await?;
+        let access_token = token.access_token.clone();
+        *cached = Some(token);
+        Ok(access_token)
+    }
+}
+
+async fn fetch_token(
+    client: &Client,
+    token_url: &str,
+    client_id: &str,
+    client_secret: &str,
+    scope: Option<&str>,
+) -> anyhow::Result<CachedToken> {
+    log::debug!("fetching oauth2 token from {}", token_url);
+    let mut form = vec![
+        ("grant_type", "client_credentials"),
+        ("client_id", client_id),
+        ("client_secret", client_secret),
+    ];
+    if let Some(scope) = scope {
+        form.push(("scope", scope));
+    }
+
+    let token_error = |err: reqwest::Error| {
+        anyhow::Error::new(err).context(format!("could not fetch oauth2 token from {}", token_url))
+    };
+    let fetched_at = Instant::now();
+    let token: TokenResponse = client
+        .post(token_url)
+        .form(&form)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(token_error)?
+        .json()
+        .await
+        .map_err(token_error)?;
+
+    let expires_at = token.expires_in.map(|expires_in| {
+        fetched_at + Duration::from_secs(expires_in).saturating_sub(EXPIRY_MARGIN)
+    });
+    Ok(CachedToken {
+        access_token: token.access_token,
+        expires_at,
+    })
+}
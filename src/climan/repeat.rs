@@ -0,0 +1,80 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
+
+use futures::{stream, StreamExt};
+use log::debug;
+use reqwest::Client;
+
+use super::model::is_expected_status;
+use super::request::{Request, Response};
+use super::template::Templates;
+
+/// aggregate outcome of sending a request several times
+#[derive(Debug, Default)]
+pub struct RepeatStats {
+    pub sent: usize,
+    /// responses with the expected status and passing assertions
+    pub succeeded: usize,
+    /// requests that got no response
+    pub errors: usize,
+    pub statuses: BTreeMap<u16, usize>,
+    pub elapsed: Duration,
+    /// total time of each response, sorted
+    pub(crate) times: Vec<Duration>,
+}
+
+impl RepeatStats {
+    fn record(&mut self, request: &Request, result: anyhow::Result<Response>) {
+        self.sent += 1;
+        let response = match result {
+            Ok(response) => response,
+            Err(err) => {
+                debug!("request {} failed: {:?}", request.name, err);
+                self.errors += 1;
+                return;
+            }
+        };
+
+        let expected = is_expected_status(request.expect_status.as_ref(), response.status_code);
+        if expected && response.assertions.failures.is_empty() {
+            self.succeeded += 1;
+        }
+        *self.statuses.entry(response.status_code).or_default() += 1;
+        self.times.push(response.time_total);
+    }
+
+    /// nearest-rank percentile of the response times, none without responses
+    pub fn percentile(&self, percentile: usize) -> Option<Duration> {
+        if self.times.is_empty() {
+            return None;
+        }
+        // the rank is the percentile of the count rounded up, starting at 1
+        let index = (percentile * self.times.len()).saturating_sub(1) / 100;
+        Some(self.times[index.min(self.times.len() - 1)])
+    }
+}
+
+/// sends the request `times` times, `concurrency` at a time
+pub async fn repeat(
+    request: &Request,
+    client: &Client,
+    templates: &Templates,
+    variables: &HashMap<String, Option<String>>,
+    times: usize,
+    concurrency: usize,
+) -> RepeatStats {
+    let start = std::time::Instant::now();
+    let mut stats = stream::iter(0..times)
+        .map(|_| request.execute(client, templates, variables, |_, _| (), |_, _, _| ()))
+        .buffer_unordered(concurrency.max(1))
+        .fold(RepeatStats::default(), |mut stats, result| async move {
+            stats.record(request, result);
+            stats
+        })
+        .await;
+    stats.elapsed = start.elapsed();
+    stats.times.sort();
+    stats
+}
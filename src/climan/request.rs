@@ -1,56 +1,408 @@
-use std::{borrow::Borrow, collections::HashMap, str::FromStr, time::Duration};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{BTreeSet, HashMap},
+    fmt,
+    path::{Path, PathBuf},
+    rc::Rc,
+    str::FromStr,
+    time::Duration,
+};
 
 use anyhow::anyhow;
-use minijinja::Environment;
+use futures::StreamExt;
+use indexmap::IndexMap;
+use jaq_core::{
+    load::{Arena, File, Loader},
+    Compiler, Ctx, Filter, Native, RcIter,
+};
+use jaq_json::Val;
 use reqwest::Client;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
 
+use super::aws;
+use super::cache::ResponseCache;
+use super::dns;
+use super::fixtures::Fixtures;
 use super::model::*;
+use super::oauth::TokenCache;
+use super::template::Templates;
 
-#[derive(Serialize, Deserialize, Debug, JsonSchema, Clone)]
+const DEFAULT_BACKOFF_MS: u64 = 500;
+const XPATH_PREFIX: &str = "xpath:";
+const REGEX_PREFIX: &str = "regex:";
+const HEADER_PREFIX: &str = "header:";
+/// extractor of the response status code
+const STATUS_EXTRACTOR: &str = "status";
+const FILE_PREFIX: &str = "file:";
+const DEFAULT_MAX_PAGES: usize = 100;
+const PROGRESS_STEP_BYTES: usize = 10 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema, Clone, Default)]
+#[serde(deny_unknown_fields)]
 pub struct Request {
     pub name: String,
     pub uri: String,
     pub method: Method,
+    /// sent in the order they are declared in
     #[serde(rename = "queryParams")]
-    pub query_params: Option<HashMap<String, ParamValue>>,
-    pub headers: Option<HashMap<String, String>>,
+    pub query_params: Option<IndexMap<String, ParamValue>>,
+    /// pre-encoded query string appended to the uri as is, e.g. for signed
+    /// urls. `queryParams` are sent after it.
+    pub query: Option<String>,
+    /// sent in the order they are declared in, a list value sends the header
+    /// once per value
+    pub headers: Option<IndexMap<String, HeaderValue>>,
     pub body: Option<Body>,
     pub authentication: Option<Authentication>,
+    /// variables set from the response: jq expressions on json bodies,
+    /// `xpath:` and `regex:` expressions, or `status` for the status code. A
+    /// jq expression yielding `null` leaves the variable unset.
     pub extractors: Option<HashMap<String, String>>,
     pub assertion: Option<String>,
+    /// checks on the response, the step fails when any of them does not hold
+    pub assertions: Option<Vec<Assertion>>,
+    /// timeout for the whole request, in seconds or as a duration string like "30s"
+    pub timeout: Option<DurationValue>,
+    /// when set, a failure of this request does not abort the workflow
+    #[serde(rename = "continueOnError")]
+    pub continue_on_error: Option<bool>,
+    pub retry: Option<Retry>,
+    /// path where the response body is streamed to, missing directories are
+    /// created. It can reference variables, like `out/{{ item }}.json` with
+    /// `forEach`. The body is not kept in memory so only the `status`
+    /// extractor and the status and header assertions are allowed.
+    pub output: Option<String>,
+    /// expression evaluated against the workflow variables, the step is skipped when false
+    pub when: Option<String>,
+    /// variables that must be set before the step runs, the workflow stops
+    /// naming the missing ones otherwise
+    pub requires: Option<Vec<String>>,
+    /// status codes considered successful, replaces the default 2xx check
+    #[serde(rename = "expectStatus")]
+    pub expect_status: Option<ExpectedStatus>,
+    /// follow redirects (default: true), only used when the request is executed
+    /// on its own, workflows configure redirects for all of their requests
+    #[serde(rename = "followRedirects")]
+    pub follow_redirects: Option<bool>,
+    /// maximum number of redirects to follow, see `followRedirects`
+    #[serde(rename = "maxRedirects")]
+    pub max_redirects: Option<usize>,
+    /// time to wait before executing the step, in workflows
+    pub delay: Option<DurationValue>,
+    /// maximum total response time in milliseconds, the step fails when exceeded
+    #[serde(rename = "maxResponseTime")]
+    pub max_response_time: Option<u64>,
+    /// path of a JSON Schema the response body must conform to
+    pub schema: Option<String>,
+    /// in workflows, executes the request once per value with the `item` and
+    /// `index` variables set. Extracted variables are stored with an `_<index>`
+    /// suffix for each iteration, the plain name holds the last iteration's value.
+    #[serde(rename = "forEach")]
+    pub for_each: Option<ForEach>,
+    /// fetches the following pages until no next cursor is found, see `Paginate`
+    pub paginate: Option<Paginate>,
+    /// labels used to select the steps of a workflow with `--tag` and `--skip-tag`
+    pub tags: Option<Vec<String>>,
+    /// directory of the spec file, relative body, multipart and schema files
+    /// are read from it
+    #[serde(skip)]
+    pub base_dir: PathBuf,
+    /// responses are recorded to or replayed from these fixtures
+    #[serde(skip)]
+    pub fixtures: Option<Rc<Fixtures>>,
+    /// GET responses with an etag are kept in this cache and revalidated
+    #[serde(skip)]
+    pub cache: Option<Rc<ResponseCache>>,
+    /// timeouts of the client, named in the error when they are exceeded
+    #[serde(skip)]
+    pub client_timeouts: ClientTimeouts,
+    /// print the progress of the bodies saved to `output` on stderr
+    #[serde(skip)]
+    pub show_progress: bool,
+    /// oauth2 tokens shared by the requests of the run, tokens are fetched for
+    /// each request without it
+    #[serde(skip)]
+    pub tokens: Option<Rc<TokenCache>>,
+    #[serde(skip)]
+    pub(crate) jq_filters: JqFilters,
+}
+
+/// timeouts set for all the requests with `--timeout` and `--connect-timeout`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ClientTimeouts {
+    pub timeout: Option<Duration>,
+    pub connect: Option<Duration>,
+}
+
+/// the jq programs of the extractors and assertions of a request, compiled
+/// once when first used
+#[derive(Clone, Default)]
+pub(crate) struct JqFilters(RefCell<HashMap<String, Rc<Filter<Native<Val>>>>>);
+
+impl JqFilters {
+    fn get(&self, path: &str) -> anyhow::Result<Rc<Filter<Native<Val>>>> {
+        if let Some(filter) = self.0.borrow().get(path) {
+            return Ok(filter.clone());
+        }
+        let filter = Rc::new(compile_jq(path)?);
+        self.0.borrow_mut().insert(path.to_string(), filter.clone());
+        Ok(filter)
+    }
+}
+
+impl fmt::Debug for JqFilters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.0.borrow().keys()).finish()
+    }
 }
 
+#[derive(Clone)]
 pub struct RequestContext<'v> {
     pub variables: &'v HashMap<String, Option<String>>,
     pub uri: String,
     pub method: Method,
-    pub query_params: HashMap<&'v String, String>,
-    pub headers: HashMap<&'v String, String>,
+    pub query_params: Vec<(&'v String, String)>,
+    pub headers: Vec<(String, String)>,
     pub body: Option<String>,
+    pub authentication: Option<Authentication>,
+    pub output: Option<String>,
+    /// 1-based position of the step and number of steps, when run as part of a workflow
+    pub step: Option<(usize, usize)>,
+}
+
+/// a response was received but it has an unexpected status or fails its
+/// assertions, as opposed to errors sending the request or building it
+#[derive(Debug)]
+pub struct UnexpectedResponse(pub String);
+
+impl fmt::Display for UnexpectedResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for UnexpectedResponse {}
+
+impl RequestContext<'_> {
+    /// the uri including the query parameters
+    pub fn url(&self) -> String {
+        if self.query_params.is_empty() {
+            return self.uri.clone();
+        }
+        let query = serde_urlencoded::to_string(&self.query_params).unwrap_or_default();
+        let separator = if self.uri.contains('?') { '&' } else { '?' };
+        format!("{}{}{}", self.uri, separator, query)
+    }
+}
+
+fn collect_namespaces<'d>(
+    element: sxd_document::dom::Element<'d>,
+    context: &mut sxd_xpath::Context<'d>,
+) {
+    for namespace in element.namespaces_in_scope() {
+        context.set_namespace(namespace.prefix(), namespace.uri());
+    }
+    for child in element.children() {
+        if let sxd_document::dom::ChildOfElement::Element(child) = child {
+            collect_namespaces(child, context);
+        }
+    }
+}
+
+/// evaluates an xpath expression against an xml document, namespace prefixes
+/// declared in the document can be used in the expression
+fn extract_xpath(xml: &str, xpath: &str) -> Option<String> {
+    let package = match sxd_document::parser::parse(xml) {
+        Ok(package) => package,
+        Err(err) => {
+            log::error!("could not parse xml response: {}", err);
+            return None;
+        }
+    };
+    let document = package.as_document();
+
+    let xpath = match sxd_xpath::Factory::new().build(xpath) {
+        Ok(Some(xpath)) => xpath,
+        Ok(None) => return None,
+        Err(err) => {
+            log::error!("invalid xpath `{}`: {}", xpath, err);
+            return None;
+        }
+    };
+
+    let mut context = sxd_xpath::Context::new();
+    for child in document.root().children() {
+        if let sxd_document::dom::ChildOfRoot::Element(element) = child {
+            collect_namespaces(element, &mut context);
+        }
+    }
+
+    match xpath.evaluate(&context, document.root()) {
+        Ok(sxd_xpath::Value::Nodeset(nodes)) => {
+            nodes.document_order_first().map(|node| node.string_value())
+        }
+        Ok(value) => Some(value.string()),
+        Err(err) => {
+            log::error!("could not evaluate xpath: {}", err);
+            None
+        }
+    }
+}
+
+/// accepts jsonpath style `$.field` paths by dropping the leading `$`, which
+/// turns them into the equivalent jq path
+fn jq_program(path: &str) -> String {
+    match path.strip_prefix('$') {
+        Some("") => ".".to_string(),
+        Some(rest) if rest.starts_with('.') => rest.to_string(),
+        Some(rest) if rest.starts_with('[') => format!(".{rest}"),
+        _ => path.to_string(),
+    }
+}
+
+/// compiles a jq program, see `jq_program` for the accepted paths
+fn compile_jq(path: &str) -> anyhow::Result<Filter<Native<Val>>> {
+    let program = jq_program(path);
+    let loader = Loader::new(jaq_std::defs().chain(jaq_json::defs()));
+    let arena = Arena::default();
+    let modules = loader
+        .load(
+            &arena,
+            File {
+                code: program.as_str(),
+                path: (),
+            },
+        )
+        .map_err(|err| anyhow!("invalid jq expression `{}`: {:?}", path, err))?;
+
+    Compiler::default()
+        .with_funs(jaq_std::funs().chain(jaq_json::funs()))
+        .compile(modules)
+        .map_err(|err| anyhow!("could not compile jq expression `{}`: {:?}", path, err))
+}
+
+/// runs a jq program against a json document and returns its first output,
+/// strings are returned without quotes and `null` yields `None`
+fn extract_jq(json: &serde_json::Value, filters: &JqFilters, path: &str) -> Option<String> {
+    let filter = match filters.get(path) {
+        Ok(filter) => filter,
+        Err(err) => {
+            log::error!("{}", err);
+            return None;
+        }
+    };
+
+    let inputs = RcIter::new(core::iter::empty());
+    let mut outputs = filter.run((Ctx::new([], &inputs), Val::from(json.clone())));
+    match outputs.next() {
+        Some(Ok(Val::Null)) | None => None,
+        Some(Ok(Val::Str(value))) => Some(value.to_string()),
+        Some(Ok(value)) => Some(value.to_string()),
+        Some(Err(err)) => {
+            log::error!("could not evaluate jq expression `{}`: {}", path, err);
+            None
+        }
+    }
 }
 
-fn replace_variables(string_value: &str, variables: &HashMap<String, Option<String>>) -> String {
-    match Environment::new().render_str(string_value, variables) {
-        Ok(value) => value,
-        Err(e) => {
-            log::error!("Error while replacing variables: {}", e);
-            string_value.to_string()
+/// returns the first capture group of the first match, or the whole match when
+/// the pattern has no capture groups
+fn extract_regex(body: &str, pattern: &str) -> Option<String> {
+    let regex = match regex::Regex::new(pattern) {
+        Ok(regex) => regex,
+        Err(err) => {
+            log::error!("invalid regex `{}`: {}", pattern, err);
+            return None;
         }
+    };
+
+    regex.captures(body).and_then(|captures| {
+        captures
+            .get(1)
+            .or_else(|| captures.get(0))
+            .map(|capture| capture.as_str().to_string())
+    })
+}
+
+/// evaluates an extractor if it applies to the response: `regex:` extractors
+/// always run against the raw body, `xpath:` extractors only on xml responses
+/// and jq extractors only on json responses. An extractor that does not match
+/// yields `Some(None)`.
+fn extract_value(
+    path: &str,
+    body: &str,
+    json: Option<&serde_json::Value>,
+    is_xml: bool,
+    filters: &JqFilters,
+) -> Option<Option<String>> {
+    if let Some(pattern) = path.strip_prefix(REGEX_PREFIX) {
+        return Some(extract_regex(body, pattern));
+    }
+
+    match (path.strip_prefix(XPATH_PREFIX), json) {
+        (Some(xpath), _) if is_xml => Some(extract_xpath(body, xpath)),
+        (None, Some(json)) => Some(extract_jq(json, filters, path)),
+        _ => None,
     }
 }
 
+/// reads a secret from disk when it has the `file:` prefix, the trailing
+/// newline most editors add is dropped
+fn read_secret(value: String) -> anyhow::Result<String> {
+    let Some(path) = value.strip_prefix(FILE_PREFIX) else {
+        return Ok(value);
+    };
+    std::fs::read_to_string(path)
+        .map(|secret| secret.trim_end_matches(['\r', '\n']).to_string())
+        .map_err(|err| anyhow!("could not read secret file {}: {}", path, err))
+}
+
+/// header values are decoded as utf-8 when valid and as latin-1 otherwise,
+/// which older servers use for e.g. `Content-Disposition` filenames
+fn header_value(value: &reqwest::header::HeaderValue) -> String {
+    match std::str::from_utf8(value.as_bytes()) {
+        Ok(value) => value.to_string(),
+        Err(_) => value
+            .as_bytes()
+            .iter()
+            .map(|&byte| char::from(byte))
+            .collect(),
+    }
+}
+
+fn is_json_content(content_type: &str) -> bool {
+    content_type.starts_with("application/json")
+}
+
+fn is_xml_content(content_type: &str) -> bool {
+    content_type.starts_with("application/xml") || content_type.starts_with("text/xml")
+}
+
+/// content types decoded as text, responses without a content type are
+/// decoded when they are valid utf-8
+fn is_text_content(content_type: &str) -> bool {
+    content_type.starts_with("text/")
+        || [
+            "json",
+            "xml",
+            "javascript",
+            "yaml",
+            "x-www-form-urlencoded",
+            "graphql",
+        ]
+        .iter()
+        .any(|text_type| content_type.contains(text_type))
+}
+
 fn evaluate_response_context(
+    templates: &Templates,
     string_value: &str,
     variables: &HashMap<String, Option<String>>,
     response: &Response,
 ) -> anyhow::Result<bool> {
-    let env = Environment::new();
-    let expression = env
-        .compile_expression(string_value)
-        .map_err(|err| anyhow!("assertion expression can not be parsed: {err}"))?;
-
     let mut all_variables = HashMap::<String, serde_json::Value>::new();
     all_variables.insert("status".to_string(), response.status_code.into());
 
@@ -61,127 +413,888 @@ fn evaluate_response_context(
         all_variables.insert(key.clone(), value.clone().unwrap_or_default().into());
     }
 
-    let result = expression.eval(all_variables)?;
-    Ok(result.is_true())
+    templates.evaluate(string_value, all_variables)
 }
 
-#[derive(Debug)]
+/// content type of content and file bodies without one: json for objects and
+/// arrays, xml for documents starting with an xml declaration
+fn detect_content_type(body: &str) -> Option<&'static str> {
+    let body = body.trim_start();
+    if body.starts_with("<?xml") {
+        Some("application/xml")
+    } else if (body.starts_with('{') || body.starts_with('['))
+        && serde_json::from_str::<serde_json::Value>(body).is_ok()
+    {
+        Some("application/json")
+    } else {
+        None
+    }
+}
+
+/// the delay asked by the `Retry-After` header, given in seconds or as an
+/// http date
+fn retry_after(headers: &HashMap<String, String>) -> Option<Duration> {
+    let value = headers.get("retry-after")?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    // dates in the past ask for an immediate retry
+    Some(
+        (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/// the json of the `base` variable with the rendered `patch` merged into it
+fn merged_json(
+    base: &str,
+    patch: &serde_json::Value,
+    templates: &Templates,
+    variables: &HashMap<String, Option<String>>,
+    dry_run: bool,
+) -> anyhow::Result<serde_json::Value> {
+    let patch = templates.render_json(patch, variables)?;
+    // dry runs extract placeholders instead of the base, the patch is shown alone
+    if dry_run {
+        return Ok(patch);
+    }
+    let base_json = variables
+        .get(base)
+        .cloned()
+        .flatten()
+        .ok_or_else(|| anyhow!("jsonMerge base variable `{base}` is not set"))?;
+    let mut merged = serde_json::from_str(&base_json)
+        .map_err(|err| anyhow!("jsonMerge base variable `{base}` is not json: {err}"))?;
+    merge_json(&mut merged, &patch);
+    Ok(merged)
+}
+
+/// evaluates the pagination `next` extractor, `header:<name>` reads a response header
+fn next_cursor(next: &str, response: &Response, filters: &JqFilters) -> Option<String> {
+    if let Some(header) = next.strip_prefix(HEADER_PREFIX) {
+        return response.headers.get(&header.trim().to_lowercase()).cloned();
+    }
+
+    let content_type = response
+        .headers
+        .get("content-type")
+        .map(|content_type| content_type.to_lowercase())
+        .unwrap_or_default();
+    let json = if is_json_content(&content_type) {
+        serde_json::from_str(&response.body).ok()
+    } else {
+        None
+    };
+    extract_value(
+        next,
+        &response.body,
+        json.as_ref(),
+        is_xml_content(&content_type),
+        filters,
+    )
+    .flatten()
+}
+
+fn serialize_millis<S: serde::Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u128(duration.as_millis())
+}
+
+fn serialize_optional_millis<S: serde::Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => serialize_millis(duration, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct AssertionResults {
+    pub passed: usize,
+    pub failures: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Default)]
 pub struct Response {
     pub status_code: u16,
+    /// the negotiated HTTP version, like `HTTP/1.1`
+    pub http_version: String,
+    /// whether the connection was encrypted, the TLS version and cipher are
+    /// not exposed by the native-tls backend
+    pub tls: bool,
+    /// the server answered `304 Not Modified` and the cached response is used
+    pub not_modified: bool,
+    /// time spent resolving the host, only known for requests opening a new
+    /// connection to a host name
+    #[serde(rename = "dns_ms", serialize_with = "serialize_optional_millis")]
+    pub dns: Option<Duration>,
+    /// time until the response headers were received, it includes connecting
+    /// and the TLS handshake of new connections, which are not timed on their own
+    #[serde(rename = "time_to_headers_ms", serialize_with = "serialize_millis")]
     pub time_to_headers: Duration,
+    #[serde(rename = "time_total_ms", serialize_with = "serialize_millis")]
     pub time_total: Duration,
     pub headers: HashMap<String, String>,
+    /// the decoded body, or a `<binary N bytes>` placeholder for binary responses
     pub body: String,
+    /// the raw body of binary responses
+    #[serde(skip)]
+    pub binary_body: Option<Vec<u8>>,
+    /// size of the received body in bytes
+    pub body_size: usize,
+    /// the parsed body of json responses
+    #[serde(skip)]
+    pub json: Option<serde_json::Value>,
     pub extracted_variables: HashMap<String, Option<String>>,
+    pub saved_to: Option<String>,
+    pub assertions: AssertionResults,
+}
+
+impl Response {
+    /// the body, indented with its keys sorted when it is json
+    pub fn pretty_body(&self) -> Cow<'_, str> {
+        self.json
+            .as_ref()
+            .and_then(|json| serde_json::to_string_pretty(json).ok())
+            .map_or(Cow::Borrowed(&self.body), Cow::Owned)
+    }
 }
 
 impl Request {
+    pub fn redirect_policy(&self) -> reqwest::redirect::Policy {
+        redirect_policy(self.follow_redirects, self.max_redirects)
+    }
+
+    /// variables read by the templates of the request, except the ones the
+    /// request sets itself (`item` and `index` for `forEach`, `cursor` when paginating)
+    pub fn referenced_variables(&self, templates: &Templates) -> BTreeSet<String> {
+        fn strings(value: &serde_json::Value, found: &mut Vec<String>) {
+            match value {
+                serde_json::Value::String(value) => found.push(value.clone()),
+                serde_json::Value::Array(values) => {
+                    values.iter().for_each(|value| strings(value, found))
+                }
+                serde_json::Value::Object(values) => {
+                    values.values().for_each(|value| strings(value, found))
+                }
+                _ => {}
+            }
+        }
+
+        let mut sources = Vec::new();
+        if let Ok(request) = serde_json::to_value(self) {
+            strings(&request, &mut sources);
+        }
+        if let Some(Body::File { file }) = &self.body {
+            sources.extend(std::fs::read_to_string(self.base_dir.join(file)).ok());
+        }
+        // `when` is an expression rather than a template
+        if let Some(when) = &self.when {
+            sources.push(format!("{{{{ {} }}}}", when));
+        }
+
+        let mut variables: BTreeSet<String> = sources
+            .iter()
+            .flat_map(|source| templates.variables(source))
+            .collect();
+        if let Some(Body::JsonMerge { base: var, .. } | Body::Variable { var }) = &self.body {
+            variables.insert(var.clone());
+        }
+        variables.extend(self.requires.iter().flatten().cloned());
+        if self.for_each.is_some() {
+            variables.remove("item");
+            variables.remove("index");
+        }
+        if self.paginate.is_some() {
+            variables.remove("cursor");
+        }
+        variables
+    }
+
+    /// checks that the extractors are valid expressions and that they, and the
+    /// assertions, can read the body, so that mistakes are reported before any
+    /// request is sent
+    pub fn validate_extractors(&self) -> anyhow::Result<()> {
+        if self.output.is_some() {
+            self.validate_output_checks()?;
+        }
+        for (name, path) in self.extractors.iter().flatten() {
+            let result = if path.trim() == STATUS_EXTRACTOR {
+                Ok(())
+            } else if let Some(pattern) = path.strip_prefix(REGEX_PREFIX) {
+                regex::Regex::new(pattern)
+                    .map(|_| ())
+                    .map_err(|err| anyhow!("invalid regex `{}`: {}", pattern, err))
+            } else if let Some(xpath) = path.strip_prefix(XPATH_PREFIX) {
+                sxd_xpath::Factory::new()
+                    .build(xpath)
+                    .map(|_| ())
+                    .map_err(|err| anyhow!("invalid xpath `{}`: {}", xpath, err))
+            } else {
+                self.jq_filters.get(path).map(|_| ())
+            };
+            result.map_err(|err| {
+                anyhow!(
+                    "invalid extractor `{}` of request {}: {}",
+                    name,
+                    self.name,
+                    err
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// bodies written to `output` are not kept, so nothing can be extracted
+    /// from them or checked on them
+    fn validate_output_checks(&self) -> anyhow::Result<()> {
+        let body_extractor = self
+            .extractors
+            .iter()
+            .flatten()
+            .find(|(_, path)| path.trim() != STATUS_EXTRACTOR);
+        if let Some((name, _)) = body_extractor {
+            return Err(anyhow!(
+                "extractor `{}` of request {} can not read the body written to `output`",
+                name,
+                self.name
+            ));
+        }
+        let body_assertion = self.assertions.iter().flatten().find(|assertion| {
+            matches!(
+                assertion,
+                Assertion::BodyContains { .. } | Assertion::JsonPath { .. }
+            )
+        });
+        if body_assertion.is_some() || self.schema.is_some() {
+            return Err(anyhow!(
+                "body assertions of request {} can not read the body written to `output`",
+                self.name
+            ));
+        }
+        Ok(())
+    }
+
+    /// checks the extractors and that the files the request reads exist
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.validate_extractors()?;
+        for file in self.files() {
+            if !file.is_file() {
+                return Err(anyhow!(
+                    "could not find file {} of request {}",
+                    file.display(),
+                    self.name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// files read when the request is sent: the body file and the multipart files
+    pub fn files(&self) -> Vec<PathBuf> {
+        match &self.body {
+            Some(Body::File { file }) => vec![self.base_dir.join(file)],
+            Some(Body::Multipart { multipart }) => multipart
+                .iter()
+                .filter_map(|field| match field {
+                    MultipartField::File { file, .. } => Some(self.base_dir.join(file)),
+                    MultipartField::Text { .. } => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
     pub async fn execute<'v>(
         &self,
         client: &Client,
+        templates: &Templates,
         variables: &'v HashMap<String, Option<String>>,
         request_action: impl Fn(&Request, &RequestContext),
         response_action: impl Fn(&Request, &RequestContext, &Response),
     ) -> anyhow::Result<Response> {
-        let (ctx, http_request) = self.request(client, variables)?;
+        let Some(paginate) = &self.paginate else {
+            return self
+                .execute_page(
+                    client,
+                    templates,
+                    variables,
+                    &request_action,
+                    &response_action,
+                )
+                .await;
+        };
+
+        let max_pages = paginate.max_pages.unwrap_or(DEFAULT_MAX_PAGES);
+        let mut page_variables = variables.clone();
+        let mut pages: Vec<serde_json::Value> = Vec::new();
+        loop {
+            let mut response = self
+                .execute_page(
+                    client,
+                    templates,
+                    &page_variables,
+                    &request_action,
+                    &response_action,
+                )
+                .await?;
+            pages.push(
+                serde_json::from_str(&response.body)
+                    .unwrap_or_else(|_| serde_json::Value::String(response.body.clone())),
+            );
+
+            let cursor = next_cursor(&paginate.next, &response, &self.jq_filters)
+                .filter(|cursor| !cursor.is_empty());
+            page_variables.insert("cursor".to_string(), cursor.clone());
+            let has_next = match (&cursor, &paginate.condition) {
+                (None, _) => false,
+                (Some(_), Some(condition)) => {
+                    evaluate_response_context(templates, condition, &page_variables, &response)?
+                }
+                (Some(_), None) => true,
+            };
+
+            if has_next && pages.len() < max_pages {
+                continue;
+            }
+            if has_next {
+                log::warn!(
+                    "request {} stopped after {} pages, see `maxPages`",
+                    self.name,
+                    pages.len()
+                );
+            }
+            response.extracted_variables.insert(
+                "pages".to_string(),
+                Some(serde_json::Value::Array(pages).to_string()),
+            );
+            return Ok(response);
+        }
+    }
 
+    /// builds the request and hands it to `request_action` without sending it.
+    /// Extractors yield `<name>` placeholders so that the following requests can
+    /// still be rendered, oauth2 tokens are not fetched and a `<token>` placeholder
+    /// is sent instead.
+    pub async fn dry_run(
+        &self,
+        client: &Client,
+        templates: &Templates,
+        variables: &HashMap<String, Option<String>>,
+        request_action: impl Fn(&Request, &RequestContext),
+    ) -> anyhow::Result<Response> {
+        let (ctx, _) = self.request(client, templates, variables, true).await?;
         request_action(self, &ctx);
+
+        let extracted_variables = self
+            .extractors
+            .iter()
+            .flatten()
+            .map(|(name, _)| (name.clone(), Some(format!("<{name}>"))))
+            .collect();
+        Ok(Response {
+            extracted_variables,
+            ..Default::default()
+        })
+    }
+
+    async fn execute_page(
+        &self,
+        client: &Client,
+        templates: &Templates,
+        variables: &HashMap<String, Option<String>>,
+        request_action: impl Fn(&Request, &RequestContext),
+        response_action: impl Fn(&Request, &RequestContext, &Response),
+    ) -> anyhow::Result<Response> {
+        let max_attempts = self
+            .retry
+            .as_ref()
+            .map(|retry| retry.max_attempts.max(1))
+            .unwrap_or(1);
+        let mut backoff = Duration::from_millis(
+            self.retry
+                .as_ref()
+                .and_then(|retry| retry.backoff_ms)
+                .unwrap_or(DEFAULT_BACKOFF_MS),
+        );
+        let mut attempt = 1;
+
+        let response = loop {
+            let (ctx, http_request) = self.request(client, templates, variables, false).await?;
+
+            request_action(self, &ctx);
+            let response = match self
+                .send(
+                    client,
+                    http_request,
+                    templates,
+                    variables,
+                    ctx.output.as_deref(),
+                )
+                .await
+            {
+                Ok(response) => response,
+                Err(err) if attempt < max_attempts && err.is::<reqwest::Error>() => {
+                    log::warn!(
+                        "request {} attempt {}/{} failed, retrying in {}ms: {}",
+                        self.name,
+                        attempt,
+                        max_attempts,
+                        backoff.as_millis(),
+                        err
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+
+            response_action(self, &ctx, &response);
+
+            match &self.retry {
+                Some(retry)
+                    if attempt < max_attempts && retry.retries_status(response.status_code) =>
+                {
+                    let delay = retry_after(&response.headers).unwrap_or(backoff);
+                    log::warn!(
+                        "request {} attempt {}/{} returned status {}, retrying in {}ms",
+                        self.name,
+                        attempt,
+                        max_attempts,
+                        response.status_code,
+                        delay.as_millis()
+                    );
+                    tokio::time::sleep(delay).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                _ => break response,
+            }
+        };
+
+        match &self.assertion {
+            Some(assertion) => {
+                if !evaluate_response_context(templates, assertion, variables, &response)? {
+                    return Err(UnexpectedResponse(format!(
+                        "Assertion failed: {}\nVariables: {variables:?}\nResponse: {response:?}",
+                        assertion
+                    ))
+                    .into());
+                } else {
+                    eprintln!("assertion `{assertion}` passed")
+                }
+            }
+            None => {}
+        }
+
+        Ok(response)
+    }
+
+    /// sends the request, or replays the recorded response, and evaluates the
+    /// extractors and assertions on the response
+    async fn send(
+        &self,
+        client: &Client,
+        mut http_request: reqwest::Request,
+        templates: &Templates,
+        variables: &HashMap<String, Option<String>>,
+        output: Option<&str>,
+    ) -> anyhow::Result<Response> {
+        let method = http_request.method().to_string();
+        let url = http_request.url().to_string();
+        // only GET responses are cached
+        let api_key_header = match &self.authentication {
+            Some(Authentication::ApiKey { key, location, .. })
+                if location.unwrap_or_default() == ApiKeyLocation::Header =>
+            {
+                Some(key.as_str())
+            }
+            _ => None,
+        };
+        let cache = self
+            .cache
+            .as_ref()
+            .filter(|_| http_request.method() == reqwest::Method::GET)
+            .map(|cache| {
+                let key = ResponseCache::key(&url, http_request.headers(), api_key_header);
+                (cache, key)
+            });
+        if let Some(etag) = cache
+            .as_ref()
+            .map(|(cache, key)| cache.etag(key))
+            .transpose()?
+            .flatten()
+        {
+            if let reqwest::header::Entry::Vacant(entry) = http_request
+                .headers_mut()
+                .entry(reqwest::header::IF_NONE_MATCH)
+            {
+                log::debug!("sending If-None-Match {} for {}", etag, url);
+                entry.insert(reqwest::header::HeaderValue::from_str(&etag)?);
+            }
+        }
+
+        let response = match &self.fixtures {
+            Some(fixtures) if fixtures.replays() => Response {
+                saved_to: output.map(str::to_string),
+                ..fixtures.replay(&self.name, &method)?
+            },
+            _ => self.receive(client, http_request, output).await?,
+        };
+        if let Some(fixtures) = &self.fixtures {
+            fixtures.record(&self.name, &method, &url, &response)?;
+        }
+        let response = match cache {
+            Some((cache, key)) => {
+                let response = cache.revalidate(&key, response)?;
+                cache.store(&key, &response)?;
+                response
+            }
+            None => response,
+        };
+        self.evaluate(response, templates, variables)
+    }
+
+    async fn receive(
+        &self,
+        client: &Client,
+        http_request: reqwest::Request,
+        output: Option<&str>,
+    ) -> anyhow::Result<Response> {
         let start_ts = std::time::Instant::now();
-        let res = client.execute(http_request).await?;
+        let (res, dns) = dns::timed(client.execute(http_request)).await;
+        let res = res.map_err(|err| self.execution_error(err))?;
         let headers_ts = std::time::Instant::now();
 
         let status = res.status().as_u16();
+        let http_version = format!("{:?}", res.version());
+        let tls = res.extensions().get::<reqwest::tls::TlsInfo>().is_some();
         let headers = res
             .headers()
             .iter()
-            .map(|(k, v)| (k.as_str().to_string(), v.to_str().unwrap_or("").to_string()))
+            .map(|(k, v)| (k.as_str().to_string(), header_value(v)))
             .collect::<HashMap<String, String>>();
 
-        let is_json = res
-            .headers()
+        let content_type = headers
             .get("content-type")
-            .map(|content_type| {
-                content_type
-                    .to_str()
-                    .expect("Content type is not a string")
-                    .to_lowercase()
-                    .starts_with("application/json")
-            })
-            .unwrap_or(false);
+            .map(|content_type| content_type.to_lowercase())
+            .unwrap_or_default();
 
-        let body_string = res.text().await?;
+        let (body_string, binary_body, body_size) = match output {
+            Some(output) => {
+                let size = self.save_body(res, output).await?;
+                (format!("<{size} bytes saved to {output}>"), None, size)
+            }
+            None => {
+                let body_bytes = res.bytes().await.map_err(|err| self.execution_error(err))?;
+                let text = if body_bytes.is_empty() || is_text_content(&content_type) {
+                    Some(String::from_utf8_lossy(&body_bytes).to_string())
+                } else if content_type.is_empty() {
+                    String::from_utf8(body_bytes.to_vec()).ok()
+                } else {
+                    None
+                };
+                match text {
+                    Some(text) => (text, None, body_bytes.len()),
+                    None => (
+                        format!("<binary {} bytes>", body_bytes.len()),
+                        Some(body_bytes.to_vec()),
+                        body_bytes.len(),
+                    ),
+                }
+            }
+        };
         let end_ts = std::time::Instant::now();
 
-        let json_value: Option<serde_json::Value> = if is_json {
-            Some(serde_json::from_str(&body_string)?)
+        Ok(Response {
+            status_code: status,
+            http_version,
+            tls,
+            dns,
+            time_to_headers: headers_ts.duration_since(start_ts),
+            time_total: end_ts.duration_since(start_ts),
+            headers,
+            body: body_string,
+            binary_body,
+            body_size,
+            saved_to: output.map(str::to_string),
+            ..Default::default()
+        })
+    }
+
+    /// runs the extractors, assertions, schema validation and response time
+    /// check on a received or replayed response
+    fn evaluate(
+        &self,
+        mut response: Response,
+        templates: &Templates,
+        variables: &HashMap<String, Option<String>>,
+    ) -> anyhow::Result<Response> {
+        let content_type = response
+            .headers
+            .get("content-type")
+            .map(|content_type| content_type.to_lowercase())
+            .unwrap_or_default();
+        let is_json = is_json_content(&content_type);
+        let is_xml = is_xml_content(&content_type);
+        let status = response.status_code;
+
+        let json_value: Option<serde_json::Value> = if is_json && response.saved_to.is_none() {
+            Some(serde_json::from_str(&response.body)?)
         } else {
             None
         };
 
-        let extracted_variables: HashMap<String, Option<String>> = match json_value.as_ref() {
-            Some(json) => self.extract_variables(json),
-            None => HashMap::new(),
-        };
+        let extracted_variables =
+            self.extract_variables(status, &response.body, json_value.as_ref(), is_xml);
+        let mut assertions = self.check_assertions(
+            status,
+            &response.headers,
+            &response.body,
+            json_value.as_ref(),
+            templates,
+            variables,
+        )?;
 
-        let time_to_headers = headers_ts.duration_since(start_ts);
-        let time_to_end = end_ts.duration_since(start_ts);
+        if let Some(schema) = &self.schema {
+            let failures =
+                self.validate_schema(schema, json_value.as_ref(), templates, variables)?;
+            if failures.is_empty() {
+                assertions.passed += 1;
+            }
+            assertions.failures.extend(failures);
+        }
 
-        let response = Response {
-            status_code: status,
-            time_to_headers,
-            time_total: time_to_end,
-            headers,
-            body: body_string,
-            extracted_variables,
-        };
+        let time_to_end = response.time_total;
+        if let Some(limit) = self.max_response_time {
+            if time_to_end.as_millis() > u128::from(limit) {
+                assertions.failures.push(format!(
+                    "took {}ms, limit {}ms",
+                    time_to_end.as_millis(),
+                    limit
+                ));
+            } else {
+                assertions.passed += 1;
+            }
+        }
 
-        response_action(self, &ctx, &response);
+        response.json = json_value;
+        response.extracted_variables = extracted_variables;
+        response.assertions = assertions;
+        Ok(response)
+    }
 
-        match &self.assertion {
-            Some(assertion) => {
-                if !evaluate_response_context(assertion, variables, &response)? {
-                    return Err(anyhow::anyhow!(
-                        "Assertion failed: {}\nVariables: {variables:?}\nResponse: {response:?}",
-                        assertion
-                    ));
-                } else {
-                    println!("assertion `{assertion}` passed")
+    /// validates the json body against the schema at `path`, returning the
+    /// validation errors. Errors loading the schema itself fail the request.
+    fn validate_schema(
+        &self,
+        path: &str,
+        json: Option<&serde_json::Value>,
+        templates: &Templates,
+        variables: &HashMap<String, Option<String>>,
+    ) -> anyhow::Result<Vec<String>> {
+        let path = templates.render(path, variables)?;
+        let content = std::fs::read_to_string(self.base_dir.join(&path))
+            .map_err(|err| anyhow!("could not read schema {}: {}", path, err))?;
+        let schema: serde_json::Value = serde_json::from_str(&content)
+            .map_err(|err| anyhow!("could not parse schema {}: {}", path, err))?;
+        let validator = jsonschema::validator_for(&schema)
+            .map_err(|err| anyhow!("invalid schema {}: {}", path, err))?;
+
+        let Some(json) = json else {
+            return Ok(vec!["schema: response body is not json".to_string()]);
+        };
+        Ok(validator
+            .iter_errors(json)
+            .map(|err| format!("schema: {} at `{}`", err, err.instance_path))
+            .collect())
+    }
+
+    fn check_assertions(
+        &self,
+        status: u16,
+        headers: &HashMap<String, String>,
+        body: &str,
+        json: Option<&serde_json::Value>,
+        templates: &Templates,
+        variables: &HashMap<String, Option<String>>,
+    ) -> anyhow::Result<AssertionResults> {
+        let mut results = AssertionResults::default();
+        for assertion in self.assertions.iter().flatten() {
+            let failure = match assertion {
+                Assertion::Status { status: expected } if !expected.matches(status) => {
+                    Some(format!("status: expected {expected}, got {status}"))
+                }
+                Assertion::Header { header, equals } => {
+                    let expected = templates.render(equals, variables)?;
+                    let actual = headers.get(&header.to_lowercase());
+                    (actual != Some(&expected)).then(|| {
+                        format!(
+                            "header {header}: expected `{expected}`, got `{}`",
+                            actual.map_or("<missing>", String::as_str)
+                        )
+                    })
+                }
+                Assertion::BodyContains { body_contains } => {
+                    let expected = templates.render(body_contains, variables)?;
+                    (!body.contains(&expected))
+                        .then(|| format!("body does not contain `{expected}`"))
+                }
+                Assertion::JsonPath { jsonpath, equals } => {
+                    let actual = json.and_then(|json| extract_jq(json, &self.jq_filters, jsonpath));
+                    let matches = match equals {
+                        serde_json::Value::Null => actual.is_none(),
+                        serde_json::Value::String(expected) => {
+                            actual.as_deref()
+                                == Some(templates.render(expected, variables)?.as_str())
+                        }
+                        expected => actual
+                            .as_deref()
+                            .and_then(|actual| {
+                                serde_json::from_str::<serde_json::Value>(actual).ok()
+                            })
+                            .is_some_and(|actual| &actual == expected),
+                    };
+                    (!matches).then(|| {
+                        format!(
+                            "{jsonpath}: expected {equals}, got {}",
+                            actual.as_deref().unwrap_or("null")
+                        )
+                    })
                 }
+                _ => None,
+            };
+
+            match failure {
+                Some(failure) => results.failures.push(failure),
+                None => results.passed += 1,
             }
-            None => {}
         }
+        Ok(results)
+    }
 
-        Ok(response)
+    /// streams the body into the `output` file without keeping it in memory,
+    /// creating its directory when missing
+    async fn save_body(&self, res: reqwest::Response, output: &str) -> anyhow::Result<usize> {
+        let save_error =
+            |err: std::io::Error| anyhow!("could not save response body to {}: {}", output, err);
+        if let Some(parent) = Path::new(output).parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(save_error)?;
+        }
+        let mut file = tokio::fs::File::create(output).await.map_err(save_error)?;
+        let mut stream = res.bytes_stream();
+        let mut size = 0;
+        let mut next_progress = PROGRESS_STEP_BYTES;
+        let mut shown_mib = 0;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|err| self.execution_error(err))?;
+            file.write_all(&chunk).await.map_err(save_error)?;
+            size += chunk.len();
+            if self.show_progress && size / (1024 * 1024) > shown_mib {
+                shown_mib = size / (1024 * 1024);
+                eprint!("\r{}: {} MiB saved to {}", self.name, shown_mib, output);
+            } else if !self.show_progress && size >= next_progress {
+                log::info!(
+                    "request {}: {} MiB saved to {}",
+                    self.name,
+                    size / (1024 * 1024),
+                    output
+                );
+                next_progress += PROGRESS_STEP_BYTES;
+            }
+        }
+        file.flush().await.map_err(save_error)?;
+        if shown_mib > 0 {
+            eprintln!();
+        }
+        Ok(size)
     }
 
-    fn extract_variables(&self, json: &serde_json::Value) -> HashMap<String, Option<String>> {
+    /// names the timeout that was exceeded, if any
+    fn execution_error(&self, err: reqwest::Error) -> anyhow::Error {
+        if !err.is_timeout() {
+            return err.into();
+        }
+        let (name, limit) = if err.is_connect() {
+            ("--connect-timeout", self.client_timeouts.connect)
+        } else if let Some(timeout) = &self.timeout {
+            ("request timeout", timeout.duration().ok())
+        } else {
+            ("--timeout", self.client_timeouts.timeout)
+        };
+        match limit {
+            Some(limit) => anyhow::Error::new(err).context(format!(
+                "request `{}` timed out after {} ({})",
+                self.name,
+                humantime::format_duration(limit),
+                name
+            )),
+            None => err.into(),
+        }
+    }
+
+    /// runs the extractors applicable to the response, see `extract_value`, the
+    /// other ones are skipped with a warning
+    fn extract_variables(
+        &self,
+        status: u16,
+        body: &str,
+        json: Option<&serde_json::Value>,
+        is_xml: bool,
+    ) -> HashMap<String, Option<String>> {
+        let mut extracted_vals: HashMap<String, Option<String>> = HashMap::new();
         if let Some(extractors) = &self.extractors {
-            let mut extracted_vals: HashMap<String, Option<String>> = HashMap::new();
             for (name, path) in extractors {
-                let s = jsonpath::Selector::new(path)
-                    .unwrap_or_else(|_| panic!("Invalid jsonpath for {}", &name));
-                let v = s
-                    .find(json)
-                    .flat_map(|v| match v {
-                        v if v.is_string() => v.as_str().map(|v| v.to_string()),
-                        v => Some(v.to_string()),
-                    })
-                    .next();
-
-                extracted_vals.insert(name.to_string(), v);
+                if path.trim() == STATUS_EXTRACTOR {
+                    extracted_vals.insert(name.to_string(), Some(status.to_string()));
+                } else if let Some(value) =
+                    extract_value(path, body, json, is_xml, &self.jq_filters)
+                {
+                    extracted_vals.insert(name.to_string(), value);
+                } else {
+                    let kind = match (json, is_xml) {
+                        (Some(_), _) => "json",
+                        (None, true) => "xml",
+                        (None, false) => "non json",
+                    };
+                    log::warn!(
+                        "skipping extractor `{}` of request {}, it does not apply to {} responses",
+                        name,
+                        self.name,
+                        kind
+                    );
+                }
             }
-            extracted_vals
-        } else {
-            HashMap::new()
         }
+        extracted_vals
     }
 
-    fn request<'v>(
+    async fn request<'v>(
         &'v self,
         client: &Client,
+        templates: &Templates,
         variables: &'v HashMap<String, Option<String>>,
+        dry_run: bool,
     ) -> anyhow::Result<(RequestContext<'v>, reqwest::Request)> {
-        let final_uri = replace_variables(&self.uri, variables);
+        let mut final_uri = templates.render(&self.uri, variables)?;
+        if let Some(query) = &self.query {
+            let query = templates.render(query, variables)?;
+            let query = query.trim_start_matches('?');
+            if !query.is_empty() {
+                let separator = if final_uri.contains('?') { '&' } else { '?' };
+                final_uri = format!("{}{}{}", final_uri, separator, query);
+            }
+        }
 
         let mut request_builder = match &self.method {
             Method::Get => client.get(&final_uri),
@@ -190,78 +1303,302 @@ impl Request {
             Method::Delete => client.delete(&final_uri),
             Method::Patch => client.patch(&final_uri),
             Method::Head => client.head(&final_uri),
+            Method::Options => client.request(reqwest::Method::OPTIONS, &final_uri),
+            Method::Trace => client.request(reqwest::Method::TRACE, &final_uri),
+            Method::Connect => client.request(reqwest::Method::CONNECT, &final_uri),
         };
 
-        let final_query_params = if let Some(query_params) = &self.query_params {
-            let params: Vec<(&String, String)> = query_params
-                .iter()
-                .flat_map(|(k, vs)| match vs {
-                    ParamValue::StringParam(v) => {
-                        vec![(k, replace_variables(v, variables))]
-                    }
-                    ParamValue::NumberParam(v) => {
-                        vec![(k, replace_variables(&v.to_string(), variables))]
-                    }
-                    ParamValue::BoolParam(v) => {
-                        vec![(k, replace_variables(&v.to_string(), variables))]
-                    }
-                    ParamValue::ListParam(vs) => vs
-                        .iter()
-                        .map(|v| (k, replace_variables(&v.to_string(), variables)))
-                        .collect(),
+        let final_authentication = self
+            .authentication
+            .as_ref()
+            .map(|authentication| -> anyhow::Result<Authentication> {
+                Ok(match authentication {
+                    Authentication::Basic { username, password } => Authentication::Basic {
+                        username: templates.render(username, variables)?,
+                        password: password
+                            .as_ref()
+                            .map(|value| read_secret(templates.render(value, variables)?))
+                            .transpose()?,
+                    },
+                    Authentication::Bearer { token } => Authentication::Bearer {
+                        token: read_secret(templates.render(token, variables)?)?,
+                    },
+                    Authentication::ApiKey {
+                        key,
+                        value,
+                        location,
+                    } => Authentication::ApiKey {
+                        key: key.clone(),
+                        value: templates.render(value, variables)?,
+                        location: *location,
+                    },
+                    Authentication::OAuth2 {
+                        token_url,
+                        client_id,
+                        client_secret,
+                        scope,
+                    } => Authentication::OAuth2 {
+                        token_url: templates.render(token_url, variables)?,
+                        client_id: templates.render(client_id, variables)?,
+                        client_secret: templates.render(client_secret, variables)?,
+                        scope: scope
+                            .as_ref()
+                            .map(|scope| templates.render(scope, variables))
+                            .transpose()?,
+                    },
+                    Authentication::AwsSigV4 {
+                        access_key,
+                        secret_key,
+                        region,
+                        service,
+                        session_token,
+                    } => Authentication::AwsSigV4 {
+                        access_key: templates.render(access_key, variables)?,
+                        secret_key: templates.render(secret_key, variables)?,
+                        region: templates.render(region, variables)?,
+                        service: templates.render(service, variables)?,
+                        session_token: session_token
+                            .as_ref()
+                            .map(|token| templates.render(token, variables))
+                            .transpose()?,
+                    },
                 })
-                .collect();
+            })
+            .transpose()?;
+        let final_authentication = match final_authentication {
+            Some(Authentication::OAuth2 { .. }) if dry_run => Some(Authentication::Bearer {
+                token: "<token>".to_string(),
+            }),
+            Some(Authentication::OAuth2 {
+                token_url,
+                client_id,
+                client_secret,
+                scope,
+            }) => Some(Authentication::Bearer {
+                token: self
+                    .tokens
+                    .clone()
+                    .unwrap_or_default()
+                    .client_credentials_token(
+                        client,
+                        &token_url,
+                        &client_id,
+                        &client_secret,
+                        scope.as_deref(),
+                    )
+                    .await?,
+            }),
+            authentication => authentication,
+        };
 
-            HashMap::from_iter(params)
-        } else {
-            HashMap::new()
+        // api keys are sent as regular query parameters or headers
+        let api_key = |api_key_location: ApiKeyLocation| match (
+            &self.authentication,
+            &final_authentication,
+        ) {
+            (
+                Some(Authentication::ApiKey { key, .. }),
+                Some(Authentication::ApiKey {
+                    value, location, ..
+                }),
+            ) if location.unwrap_or_default() == api_key_location => Some((key, value.clone())),
+            _ => None,
         };
+
+        // list values repeat the key once per value
+        let mut final_query_params: Vec<(&String, String)> = self
+            .query_params
+            .iter()
+            .flatten()
+            .flat_map(|(k, vs)| match vs {
+                ParamValue::StringParam(v) => vec![(k, v.clone())],
+                ParamValue::NumberParam(v) => vec![(k, v.to_string())],
+                ParamValue::BoolParam(v) => vec![(k, v.to_string())],
+                ParamValue::ListParam(vs) => vs
+                    .iter()
+                    .map(|v| match v {
+                        serde_json::Value::String(v) => (k, v.clone()),
+                        v => (k, v.to_string()),
+                    })
+                    .collect(),
+            })
+            .map(|(k, v)| Ok((k, templates.render(&v, variables)?)))
+            .collect::<anyhow::Result<_>>()?;
+        if let Some((key, value)) = api_key(ApiKeyLocation::Query) {
+            final_query_params.retain(|(k, _)| *k != key);
+            final_query_params.push((key, value));
+        }
         request_builder = request_builder.query(&final_query_params);
 
-        let final_headers = if let Some(headers) = &self.headers {
-            let header_it = headers
-                .iter()
-                .map(|(k, v)| (k, replace_variables(v, variables)));
+        let mut final_headers = Vec::new();
+        for (k, vs) in self.headers.iter().flatten() {
+            for v in vs.values() {
+                final_headers.push((k.clone(), templates.render(v, variables)?));
+            }
+        }
+        if let Some((key, value)) = api_key(ApiKeyLocation::Header) {
+            final_headers.retain(|(k, _)| k != key);
+            final_headers.push((key.clone(), value));
+        }
+        // values are left out of the errors, they may hold secrets
+        let mut header_map = reqwest::header::HeaderMap::new();
+        for (k, v) in &final_headers {
+            let name = reqwest::header::HeaderName::from_str(k)
+                .map_err(|err| anyhow!("invalid header name `{}`: {}", k, err))?;
+            let value = reqwest::header::HeaderValue::from_str(v)
+                .map_err(|err| anyhow!("invalid value for header `{}`: {}", k, err))?;
+            header_map.append(name, value);
+        }
+        request_builder = request_builder.headers(header_map);
 
-            HashMap::from_iter(header_it)
-        } else {
-            HashMap::new()
-        };
-        request_builder = request_builder.headers(reqwest::header::HeaderMap::from_iter(
-            final_headers.iter().map(|(k, v)| {
-                (
-                    reqwest::header::HeaderName::from_str(k).unwrap(),
-                    reqwest::header::HeaderValue::from_str(v).unwrap(),
-                )
-            }),
-        ));
+        let mut default_content_type = None;
+        let final_body = match &self.body {
+            Some(Body::Form { form }) => {
+                let values: IndexMap<&String, String> = form
+                    .iter()
+                    .map(|(k, v)| Ok((k, templates.render(v, variables)?)))
+                    .collect::<anyhow::Result<_>>()?;
+                request_builder = request_builder.form(&values);
+                Some(serde_urlencoded::to_string(&values)?)
+            }
+            Some(Body::Multipart { multipart }) => {
+                let mut form = reqwest::multipart::Form::new();
+                let mut summary: Vec<String> = Vec::new();
+                for field in multipart {
+                    match field {
+                        MultipartField::Text { name, value } => {
+                            let value = templates.render(value, variables)?;
+                            summary.push(format!("{name}={value}"));
+                            form = form.text(name.clone(), value);
+                        }
+                        MultipartField::File {
+                            name,
+                            file,
+                            filename,
+                            content_type,
+                        } => {
+                            let content =
+                                tokio::fs::read(self.base_dir.join(file))
+                                    .await
+                                    .map_err(|err| {
+                                        anyhow!("could not read multipart file {}: {}", file, err)
+                                    })?;
+                            let filename = filename.clone().or_else(|| {
+                                Path::new(file)
+                                    .file_name()
+                                    .map(|name| name.to_string_lossy().to_string())
+                            });
 
-        let final_body = self.body.as_ref().map(|body| {
-            let body_string = String::from_utf8_lossy(&body.content()).to_string();
-            replace_variables(&body_string, variables)
-        });
+                            let mut part = reqwest::multipart::Part::bytes(content);
+                            if let Some(filename) = filename {
+                                part = part.file_name(filename);
+                            }
+                            if let Some(content_type) = content_type {
+                                part = part.mime_str(content_type)?;
+                            }
+                            summary.push(field.summary());
+                            form = form.part(name.clone(), part);
+                        }
+                    }
+                }
+                request_builder = request_builder.multipart(form);
+                Some(summary.join("\n"))
+            }
+            Some(Body::GraphQl {
+                query,
+                variables: graphql_variables,
+                operation_name,
+            }) => {
+                let query = templates.render(query, variables)?;
+                let graphql_variables = graphql_variables
+                    .as_ref()
+                    .map(|graphql_variables| templates.render_json(graphql_variables, variables))
+                    .transpose()?;
+                let payload = graphql_payload(&query, &graphql_variables, operation_name);
+                request_builder = request_builder.json(&payload);
+                Some(payload.to_string())
+            }
+            Some(Body::Json { json }) => {
+                let json = templates.render_json(json, variables)?;
+                request_builder = request_builder.json(&json);
+                Some(json.to_string())
+            }
+            Some(Body::JsonMerge { base, patch }) => {
+                let merged = merged_json(base, patch, templates, variables, dry_run)?;
+                request_builder = request_builder.json(&merged);
+                Some(merged.to_string())
+            }
+            Some(Body::Variable { var }) => {
+                let body_string = variables
+                    .get(var)
+                    .cloned()
+                    .flatten()
+                    .ok_or_else(|| anyhow!("body variable `{var}` is not set"))?;
+                default_content_type = detect_content_type(&body_string);
+                request_builder = request_builder.body(body_string.clone());
+                Some(body_string)
+            }
+            Some(Body::Ndjson { items }) => {
+                let items = items
+                    .iter()
+                    .map(|item| templates.render_json(item, variables))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                let body_string = ndjson(&items);
+                default_content_type = Some("application/x-ndjson");
+                request_builder = request_builder.body(body_string.clone());
+                Some(body_string)
+            }
+            Some(body) => {
+                let body_string =
+                    String::from_utf8_lossy(&body.content(&self.base_dir)?).to_string();
+                let body_string = templates.render(&body_string, variables)?;
+                default_content_type = detect_content_type(&body_string);
+                request_builder = request_builder.body(body_string.clone());
+                Some(body_string)
+            }
+            None => None,
+        };
 
-        if let Some(body) = final_body.borrow() {
-            request_builder = request_builder.body(body.clone());
+        if let Some(timeout) = &self.timeout {
+            request_builder = request_builder.timeout(timeout.duration()?);
         }
 
-        if let Some(authentication) = &self.authentication {
+        if let Some(authentication) = &final_authentication {
             match authentication {
                 Authentication::Basic { username, password } => {
-                    request_builder = request_builder.basic_auth(
-                        replace_variables(username, variables),
-                        password
-                            .clone()
-                            .map(|value| replace_variables(&value, variables)),
-                    )
+                    request_builder = request_builder.basic_auth(username, password.as_ref())
                 }
                 Authentication::Bearer { token } => {
-                    request_builder =
-                        request_builder.bearer_auth(replace_variables(token, variables))
+                    request_builder = request_builder.bearer_auth(token)
                 }
+                // api keys are already set, oauth2 resolves to a bearer token
+                // and aws requests are signed once built
+                Authentication::ApiKey { .. }
+                | Authentication::OAuth2 { .. }
+                | Authentication::AwsSigV4 { .. } => {}
             }
         };
 
+        let mut http_request = request_builder.build()?;
+        let content_type = http_request
+            .headers_mut()
+            .entry(reqwest::header::CONTENT_TYPE);
+        if let (Some(default), reqwest::header::Entry::Vacant(entry)) =
+            (default_content_type, content_type)
+        {
+            entry.insert(reqwest::header::HeaderValue::from_static(default));
+        }
+        // forms, json and multipart bodies get their content type from reqwest,
+        // it is shown with the headers of the request
+        if !final_headers
+            .iter()
+            .any(|(k, _)| k.eq_ignore_ascii_case("content-type"))
+        {
+            if let Some(content_type) = http_request.headers().get(reqwest::header::CONTENT_TYPE) {
+                final_headers.push(("Content-Type".to_string(), header_value(content_type)));
+            }
+        }
+
         let request_context: RequestContext<'v> = RequestContext {
             variables,
             uri: final_uri,
@@ -269,8 +1606,33 @@ impl Request {
             query_params: final_query_params,
             headers: final_headers,
             body: final_body,
+            authentication: final_authentication,
+            output: self
+                .output
+                .as_ref()
+                .map(|output| templates.render(output, variables))
+                .transpose()?,
+            step: None,
         };
 
-        Ok((request_context, request_builder.build()?))
+        if let Some(Authentication::AwsSigV4 {
+            access_key,
+            secret_key,
+            region,
+            service,
+            session_token,
+        }) = &request_context.authentication
+        {
+            aws::sign_request(
+                &mut http_request,
+                access_key,
+                secret_key,
+                session_token.as_deref(),
+                region,
+                service,
+            )?;
+        }
+
+        Ok((request_context, http_request))
     }
 }
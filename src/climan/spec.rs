@@ -0,0 +1,53 @@
+use anyhow::anyhow;
+use serde::de::DeserializeOwned;
+
+const UNKNOWN_FIELD: &str = "unknown field `";
+
+/// parses a yaml spec, misspelled fields are reported with the closest valid
+/// field name instead of the list of all of them
+pub fn from_yaml<T: DeserializeOwned>(content: &str) -> anyhow::Result<T> {
+    serde_yaml::from_str(content).map_err(|err| {
+        let message = err.to_string();
+        anyhow!(suggest_field(&message).unwrap_or(message))
+    })
+}
+
+/// rewrites serde's `unknown field `x`, expected one of `a`, `b`` errors when
+/// one of the expected fields is close enough to the unknown one
+fn suggest_field(message: &str) -> Option<String> {
+    let start = message.find(UNKNOWN_FIELD)?;
+    // quoted names are at the odd positions: the unknown field, then the expected ones
+    let quoted: Vec<&str> = message[start..].split('`').skip(1).step_by(2).collect();
+    let (field, expected) = quoted.split_first()?;
+    let max_distance = 2.max(field.len() / 3);
+    let closest = expected
+        .iter()
+        .map(|candidate| (distance(field, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)?
+        .1;
+
+    let list_start = start + UNKNOWN_FIELD.len() + field.len() + 1;
+    let list_end = start + message[start..].rfind('`')? + 1;
+    Some(format!(
+        "{}{}, did you mean `{}`?",
+        &message[..list_start],
+        &message[list_end..],
+        closest
+    ))
+}
+
+/// levenshtein distance between two strings
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
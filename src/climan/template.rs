@@ -0,0 +1,204 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+};
+
+use anyhow::anyhow;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use minijinja::{value::ValueKind, Environment, Error, ErrorKind, UndefinedBehavior, Value};
+use serde::Serialize;
+
+/// functions registered below and the minijinja builtin ones, they are not variables
+const FUNCTIONS: [&str; 9] = [
+    "uuid",
+    "now",
+    "base64",
+    "base64_decode",
+    "env",
+    "range",
+    "dict",
+    "namespace",
+    "debug",
+];
+
+/// template environment used for variable substitution and expressions.
+///
+/// Besides the minijinja builtins (`default`, `lower`, `upper`, `trim`,
+/// `replace`, `join`, `length`, ...) the `tojson` and `urlencode` filters are
+/// available, along with these functions:
+/// * `uuid()`: a random v4 uuid
+/// * `now(format)`: the current UTC time, RFC 3339 unless a strftime format is given
+/// * `base64(value)` / `base64_decode(value)`, also available as filters
+/// * `env(name)`: a process environment variable, undefined when not set
+fn environment() -> Environment<'static> {
+    let mut env = Environment::new();
+    env.add_function("uuid", uuid);
+    env.add_function("now", now);
+    env.add_function("base64", base64_encode);
+    env.add_filter("base64", base64_encode);
+    env.add_function("base64_decode", base64_decode);
+    env.add_filter("base64_decode", base64_decode);
+    env.add_function("env", env_var);
+    env
+}
+
+fn uuid() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+fn now(format: Option<String>) -> Result<String, Error> {
+    let now = chrono::Utc::now();
+    let Some(format) = format else {
+        return Ok(now.to_rfc3339());
+    };
+
+    let mut formatted = String::new();
+    write!(formatted, "{}", now.format(&format)).map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidOperation,
+            format!("invalid time format `{format}`"),
+        )
+    })?;
+    Ok(formatted)
+}
+
+fn base64_encode(value: String) -> String {
+    STANDARD.encode(value)
+}
+
+fn base64_decode(value: String) -> Result<String, Error> {
+    let decoded = STANDARD.decode(value).map_err(|err| {
+        Error::new(
+            ErrorKind::InvalidOperation,
+            format!("invalid base64: {err}"),
+        )
+    })?;
+    String::from_utf8(decoded).map_err(|err| {
+        Error::new(
+            ErrorKind::InvalidOperation,
+            format!("decoded base64 is not utf-8: {err}"),
+        )
+    })
+}
+
+fn env_var(name: String) -> Value {
+    std::env::var(name)
+        .map(Value::from)
+        .unwrap_or(Value::UNDEFINED)
+}
+
+/// renders the templates and evaluates the expressions found in the specs,
+/// a single instance is shared by all the requests of a run
+pub struct Templates {
+    env: Environment<'static>,
+    strict: bool,
+}
+
+impl Templates {
+    /// in strict mode undefined variables and invalid templates are errors,
+    /// otherwise undefined variables render as empty strings and invalid
+    /// templates are logged and used as they are
+    pub fn new(strict: bool) -> Templates {
+        let mut env = environment();
+        if strict {
+            env.set_undefined_behavior(UndefinedBehavior::Strict);
+        }
+        Templates { env, strict }
+    }
+
+    pub fn render(
+        &self,
+        template: &str,
+        variables: &HashMap<String, Option<String>>,
+    ) -> anyhow::Result<String> {
+        match self.env.render_str(template, variables) {
+            Ok(value) => Ok(value),
+            Err(err) if self.strict => Err(anyhow!("could not render `{}`: {}", template, err)),
+            Err(err) => {
+                log::error!("Error while replacing variables: {}", err);
+                Ok(template.to_string())
+            }
+        }
+    }
+
+    /// renders the strings of a json value at any depth, keys are left as
+    /// they are. Strings made of a single `{{ expression }}` evaluating to a
+    /// number, a boolean, a list or a map keep its type, e.g. `{{ id | int }}`
+    pub fn render_json(
+        &self,
+        value: &serde_json::Value,
+        variables: &HashMap<String, Option<String>>,
+    ) -> anyhow::Result<serde_json::Value> {
+        Ok(match value {
+            serde_json::Value::String(value) => match self.typed_value(value, variables) {
+                Some(value) => value,
+                None => serde_json::Value::String(self.render(value, variables)?),
+            },
+            serde_json::Value::Array(values) => serde_json::Value::Array(
+                values
+                    .iter()
+                    .map(|value| self.render_json(value, variables))
+                    .collect::<anyhow::Result<_>>()?,
+            ),
+            serde_json::Value::Object(values) => serde_json::Value::Object(
+                values
+                    .iter()
+                    .map(|(key, value)| Ok((key.clone(), self.render_json(value, variables)?)))
+                    .collect::<anyhow::Result<_>>()?,
+            ),
+            value => value.clone(),
+        })
+    }
+
+    /// the json value of a template holding a single expression, when it is
+    /// not a string or undefined
+    fn typed_value(
+        &self,
+        template: &str,
+        variables: &HashMap<String, Option<String>>,
+    ) -> Option<serde_json::Value> {
+        let expression = template
+            .trim()
+            .strip_prefix("{{")?
+            .strip_suffix("}}")?
+            .trim();
+        // whitespace control markers (`{{-`) are not part of the expression
+        if expression.contains("{{")
+            || expression.contains("}}")
+            || expression.starts_with(['-', '+'])
+            || expression.ends_with(['-', '+'])
+        {
+            return None;
+        }
+        let value = self
+            .env
+            .compile_expression(expression)
+            .and_then(|expression| expression.eval(variables))
+            .ok()?;
+        match value.kind() {
+            ValueKind::Bool | ValueKind::Number | ValueKind::Seq | ValueKind::Map => {
+                serde_json::to_value(&value).ok()
+            }
+            _ => None,
+        }
+    }
+
+    /// names of the variables read by a template, invalid templates read none
+    pub fn variables(&self, template: &str) -> HashSet<String> {
+        self.env
+            .template_from_str(template)
+            .map(|template| template.undeclared_variables(false))
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|name| !FUNCTIONS.contains(&name.as_str()))
+            .collect()
+    }
+
+    pub fn evaluate<S: Serialize>(&self, expression: &str, context: S) -> anyhow::Result<bool> {
+        let expression = self
+            .env
+            .compile_expression(expression)
+            .map_err(|err| anyhow!("expression `{}` can not be parsed: {}", expression, err))?;
+        Ok(expression.eval(context)?.is_true())
+    }
+}
@@ -1,11 +1,45 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    borrow::Cow,
+    cell::Cell,
+    collections::{BTreeSet, HashMap},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
-use log::debug;
-use reqwest::{Client, StatusCode};
+use anyhow::anyhow;
+use futures::{future::LocalBoxFuture, stream, FutureExt, StreamExt};
+use indexmap::IndexMap;
+use log::{debug, error, info, warn};
+use reqwest::Client;
 use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
 
-use super::request::{Request, RequestContext, Response};
+use super::cache::ResponseCache;
+use super::fixtures::Fixtures;
+use super::interrupt::Interrupt;
+use super::junit::JunitReport;
+use super::model::{
+    is_expected_status, redirect_policy, Authentication, ClientCertificate, DurationValue,
+    ExpectedStatus, HeaderValue,
+};
+use super::oauth::TokenCache;
+use super::request::{ClientTimeouts, Request, RequestContext, Response, UnexpectedResponse};
+use super::spec;
+use super::template::Templates;
+
+/// variables holding the body and status of the last response
+pub const PREVIOUS_BODY: &str = "previous_body";
+pub const PREVIOUS_STATUS: &str = "previous_status";
+
+/// name of a variable set after each step, the characters of the step name
+/// that cannot be part of a variable name are replaced with `_`
+fn step_variable(step: &str, suffix: &str) -> String {
+    let step: String = step
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("{step}_{suffix}")
+}
 
 #[derive(Debug, Clone)]
 pub struct WorkflowContext {
@@ -22,24 +56,658 @@ impl WorkflowContext {
     fn update<T: IntoIterator<Item = (String, Option<String>)>>(&mut self, variables: T) {
         self.variables.extend(variables);
     }
+
+    fn evaluate(&self, templates: &Templates, expression: &str) -> anyhow::Result<bool> {
+        templates
+            .evaluate(expression, &self.variables)
+            .map_err(|err| anyhow!("could not evaluate when condition: {err}"))
+    }
+}
+
+/// selects the steps of a workflow by their tags, untagged steps only run
+/// when no tag is required
+#[derive(Debug, Default, Clone)]
+pub struct TagFilter {
+    /// steps with at least one of these tags run, all do when empty
+    pub tags: Vec<String>,
+    /// steps with any of these tags are skipped
+    pub skip_tags: Vec<String>,
+}
+
+impl TagFilter {
+    fn selects(&self, request: &Request) -> bool {
+        let tags = request.tags.as_deref().unwrap_or_default();
+        (self.tags.is_empty() || tags.iter().any(|tag| self.tags.contains(tag)))
+            && !tags.iter().any(|tag| self.skip_tags.contains(tag))
+    }
 }
 
 #[derive(Debug)]
 pub struct WorkflowResult {
     pub responses: Vec<Response>,
     pub final_variables: HashMap<String, Option<String>>,
+    pub failed_steps: Vec<String>,
+    /// steps whose responses all have the expected status and pass their assertions
+    pub passed_steps: Vec<String>,
+    /// the workflow was stopped with Ctrl-C, the remaining steps were not executed
+    pub interrupted: bool,
+}
+
+/// a workflow step: an inline request, a reference to a request file or
+/// another workflow to run
+#[derive(Serialize, Debug, JsonSchema, Clone)]
+#[serde(untagged)]
+pub enum Step {
+    /// path of a workflow file, relative to this one. The included workflow
+    /// starts with the current variables and the ones it ends with are
+    /// available to the following steps, its client settings are ignored.
+    /// It inherits the request defaults it does not set itself.
+    Include {
+        include: String,
+    },
+    /// path of a request file, relative to the workflow file
+    Reference {
+        #[serde(rename = "ref")]
+        reference: String,
+    },
+    Request(Box<Request>),
+}
+
+// steps are told apart by their keys instead of trying each variant, so that
+// errors in inline requests are reported as such
+impl<'de> Deserialize<'de> for Step {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = serde_yaml::Value::deserialize(deserializer)?;
+        if let Some(include) = value.get("include") {
+            let include = include
+                .as_str()
+                .ok_or_else(|| D::Error::custom("`include` must be the path of a workflow file"))?;
+            return Ok(Step::Include {
+                include: include.to_string(),
+            });
+        }
+        if let Some(reference) = value.get("ref") {
+            let reference = reference
+                .as_str()
+                .ok_or_else(|| D::Error::custom("`ref` must be the path of a request file"))?;
+            return Ok(Step::Reference {
+                reference: reference.to_string(),
+            });
+        }
+        serde_yaml::from_value(value)
+            .map(|request| Step::Request(Box::new(request)))
+            .map_err(D::Error::custom)
+    }
+}
+
+impl Step {
+    /// the request of the step, referenced request files are read from `base_dir`.
+    /// Includes have no request.
+    fn request(&self, base_dir: &Path) -> anyhow::Result<Option<Cow<'_, Request>>> {
+        match self {
+            Step::Include { .. } => Ok(None),
+            Step::Request(request) => Ok(Some(Cow::Borrowed(request))),
+            Step::Reference { reference } => {
+                let path = base_dir.join(reference);
+                let content = std::fs::read_to_string(&path).map_err(|err| {
+                    anyhow!("could not read request file {}: {}", path.display(), err)
+                })?;
+                let mut request: Request = spec::from_yaml(&content).map_err(|err| {
+                    anyhow!("could not parse request file {}: {}", path.display(), err)
+                })?;
+                request.base_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+                Ok(Some(Cow::Owned(request)))
+            }
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema, Default)]
+#[serde(deny_unknown_fields)]
 pub struct Workflow {
     pub name: String,
-    requests: Vec<Request>,
+    requests: Vec<Step>,
+    /// maximum number of requests executed at the same time (default: 1)
+    pub concurrency: Option<usize>,
+    /// follow redirects (default: true)
+    #[serde(rename = "followRedirects")]
+    pub follow_redirects: Option<bool>,
+    /// maximum number of redirects to follow
+    #[serde(rename = "maxRedirects")]
+    pub max_redirects: Option<usize>,
+    /// client certificate presented to servers requiring mutual TLS
+    #[serde(rename = "clientCertificate")]
+    pub client_certificate: Option<ClientCertificate>,
+    /// time to wait between steps, steps can override it with their own `delay`
+    pub delay: Option<DurationValue>,
+    /// fail on undefined variables and invalid templates, like `--strict`
+    pub strict: Option<bool>,
+    /// prepended to the uri of the requests starting with `/`
+    #[serde(rename = "baseUrl")]
+    pub base_url: Option<String>,
+    /// headers sent by all the requests, the requests' own headers override them
+    pub headers: Option<IndexMap<String, HeaderValue>>,
+    /// authentication of the requests that do not set their own
+    pub authentication: Option<Authentication>,
+    /// status codes considered successful for all the requests, like
+    /// `[200, 201, 204]` or `2xx` (default), the requests' `expectStatus`
+    /// overrides it
+    pub success: Option<ExpectedStatus>,
+    /// directory referenced files are relative to
+    #[serde(skip)]
+    base_dir: PathBuf,
+    /// the workflow files that included this one, followed by this one
+    #[serde(skip)]
+    include_chain: Vec<PathBuf>,
+    /// steps not selected by the filter are skipped, included workflows inherit it
+    #[serde(skip)]
+    pub tag_filter: TagFilter,
+    /// records the outcome of each step, shared with the included workflows
+    #[serde(skip)]
+    pub junit: Option<Rc<JunitReport>>,
+    /// responses are recorded to or replayed from these fixtures, shared with
+    /// the included workflows
+    #[serde(skip)]
+    pub fixtures: Option<Rc<Fixtures>>,
+    /// cache of the GET responses with an etag, shared with the included workflows
+    #[serde(skip)]
+    pub cache: Option<Rc<ResponseCache>>,
+    /// timeouts of the client, named in the errors of the requests
+    #[serde(skip)]
+    pub client_timeouts: ClientTimeouts,
+    /// print the progress of the bodies saved by the requests on stderr
+    #[serde(skip)]
+    pub show_progress: bool,
+    /// oauth2 tokens fetched during the run, shared with the included workflows
+    #[serde(skip)]
+    pub tokens: Option<Rc<TokenCache>>,
+    /// Ctrl-C skips the remaining steps, it is not handled without it
+    #[serde(skip)]
+    pub interrupt: Option<Interrupt>,
+}
+
+/// loads the variables of the given yaml files, later files override earlier ones
+pub async fn load_variable_files(
+    files: Vec<PathBuf>,
+) -> anyhow::Result<HashMap<String, Option<String>>> {
+    let mut variables: HashMap<String, Option<String>> = HashMap::new();
+
+    for file in files {
+        let filename = file.display().to_string();
+        debug!("loading context from file: {}", filename);
+        let file_variables: HashMap<String, Option<String>> = match tokio::fs::read(file).await {
+            Ok(context_content) => serde_yaml::from_slice(&context_content)?,
+            Err(e) => {
+                return Err(anyhow::anyhow!(
+                    "failed to read context file {}: {}",
+                    filename,
+                    e
+                ))
+            }
+        };
+        variables.extend(file_variables);
+    }
+    Ok(variables)
+}
+
+/// adds `headers` to the default ones, replacing the defaults with the same
+/// name regardless of case
+fn merge_headers(
+    defaults: Option<IndexMap<String, HeaderValue>>,
+    headers: Option<IndexMap<String, HeaderValue>>,
+) -> Option<IndexMap<String, HeaderValue>> {
+    let Some(mut merged) = defaults else {
+        return headers;
+    };
+    for (name, value) in headers.into_iter().flatten() {
+        merged.retain(|default, _| !default.eq_ignore_ascii_case(&name));
+        merged.insert(name, value);
+    }
+    Some(merged)
+}
+
+/// counts the steps started so far, included workflows share the count
+struct Progress {
+    started: Cell<usize>,
+    total: usize,
+}
+
+impl Progress {
+    fn new(total: usize) -> Progress {
+        Progress {
+            started: Cell::new(0),
+            total,
+        }
+    }
+
+    /// position of the next step and number of steps
+    fn next(&self) -> (usize, usize) {
+        self.started.set(self.started.get() + 1);
+        (self.started.get(), self.total)
+    }
+}
+
+/// logs how a step ended, with the request, step and outcome as fields for the
+/// json log format. Failed steps are warnings and the ones that could not be
+/// executed errors, so that they are logged with the default level too.
+fn log_outcome(request: &Request, step: (usize, usize), outcome: &str) {
+    let level = match outcome {
+        "error" => log::Level::Error,
+        "failed" => log::Level::Warn,
+        _ => log::Level::Info,
+    };
+    log::log!(
+        level,
+        request = request.name.as_str(),
+        step = step.0,
+        outcome = outcome;
+        "step {}/{} {}: {}", step.0, step.1, request.name, outcome
+    );
+}
+
+/// executes the request, or only builds it in dry-run mode
+async fn send_request(
+    request: &Request,
+    variables: &HashMap<String, Option<String>>,
+    client: &Client,
+    templates: &Templates,
+    dry_run: bool,
+    request_action: &impl Fn(&Request, &RequestContext),
+    response_action: &impl Fn(&Request, &RequestContext, &Response),
+) -> anyhow::Result<Response> {
+    if dry_run {
+        return request
+            .dry_run(client, templates, variables, request_action)
+            .await;
+    }
+    request
+        .execute(
+            client,
+            templates,
+            variables,
+            request_action,
+            response_action,
+        )
+        .await
+}
+
+/// executes a single workflow step, returns no responses when the step is skipped
+/// and one per iteration for `forEach` steps
+#[allow(clippy::too_many_arguments)]
+async fn execute_step(
+    request: &Request,
+    step: (usize, usize),
+    delay: Option<&DurationValue>,
+    context: &WorkflowContext,
+    client: &Client,
+    templates: &Templates,
+    dry_run: bool,
+    request_action: &impl Fn(&Request, &RequestContext),
+    response_action: &impl Fn(&Request, &RequestContext, &Response),
+) -> anyhow::Result<Vec<Response>> {
+    if let Some(when) = &request.when {
+        if !context.evaluate(templates, when)? {
+            info!(
+                "skipping request {}, condition `{}` is false",
+                request.name, when
+            );
+            return Ok(vec![]);
+        }
+    }
+
+    let missing: Vec<&String> = request
+        .requires
+        .iter()
+        .flatten()
+        .filter(|name| !matches!(context.variables.get(*name), Some(Some(_))))
+        .collect();
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "request {} requires variables that are not set: {}",
+            request.name,
+            missing
+                .iter()
+                .map(|name| format!("`{name}`"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+
+    if let (Some(delay), false) = (delay, dry_run) {
+        let delay = delay.duration()?;
+        debug!("waiting {:?} before request {}", delay, request.name);
+        tokio::time::sleep(delay).await;
+    }
+
+    // the actions see the position of the step in the workflow
+    let request_action = |request: &Request, request_context: &RequestContext| {
+        request_action(
+            request,
+            &RequestContext {
+                step: Some(step),
+                ..request_context.clone()
+            },
+        )
+    };
+    let response_action =
+        |request: &Request, request_context: &RequestContext, response: &Response| {
+            response_action(
+                request,
+                &RequestContext {
+                    step: Some(step),
+                    ..request_context.clone()
+                },
+                response,
+            )
+        };
+
+    debug!("executing request: {:?}", request);
+    let Some(for_each) = &request.for_each else {
+        let response = send_request(
+            request,
+            &context.variables,
+            client,
+            templates,
+            dry_run,
+            &request_action,
+            &response_action,
+        )
+        .await?;
+        return Ok(vec![response]);
+    };
+
+    let mut responses = Vec::new();
+    for (index, item) in for_each.items(&context.variables)?.into_iter().enumerate() {
+        let mut variables = context.variables.clone();
+        variables.insert("item".to_string(), Some(item));
+        variables.insert("index".to_string(), Some(index.to_string()));
+
+        let mut response = send_request(
+            request,
+            &variables,
+            client,
+            templates,
+            dry_run,
+            &request_action,
+            &response_action,
+        )
+        .await?;
+        let suffixed: Vec<(String, Option<String>)> = response
+            .extracted_variables
+            .iter()
+            .map(|(name, value)| (format!("{name}_{index}"), value.clone()))
+            .collect();
+        response.extracted_variables.extend(suffixed);
+        responses.push(response);
+    }
+    Ok(responses)
 }
 
 impl Workflow {
+    pub fn new(name: String, requests: Vec<Request>) -> Workflow {
+        Workflow {
+            name,
+            requests: requests
+                .into_iter()
+                .map(|request| Step::Request(Box::new(request)))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// reads a workflow file, the files it references are relative to its directory
+    pub fn load(path: &Path) -> anyhow::Result<Workflow> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| anyhow!("could not read workflow {}: {}", path.display(), err))?;
+        let mut workflow: Workflow = spec::from_yaml(&content)
+            .map_err(|err| anyhow!("could not parse workflow {}: {}", path.display(), err))?;
+        workflow.base_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        for step in &mut workflow.requests {
+            if let Step::Request(request) = step {
+                request.base_dir = workflow.base_dir.clone();
+            }
+        }
+        workflow.include_chain = vec![path.canonicalize()?];
+        Ok(workflow)
+    }
+
+    /// parses a workflow that is not read from a file, e.g. from stdin. The
+    /// files it references are relative to the current directory.
+    pub fn parse(content: &str) -> anyhow::Result<Workflow> {
+        spec::from_yaml(content).map_err(|err| anyhow!("could not parse workflow: {}", err))
+    }
+
+    /// loads an included workflow, failing when it ends up including itself
+    fn include(&self, include: &str) -> anyhow::Result<Workflow> {
+        let mut workflow = Workflow::load(&self.base_dir.join(include))?;
+        let mut include_chain = self.include_chain.clone();
+        include_chain.append(&mut workflow.include_chain);
+        if self
+            .include_chain
+            .contains(&include_chain[include_chain.len() - 1])
+        {
+            let chain: Vec<String> = include_chain
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect();
+            return Err(anyhow!("include cycle: {}", chain.join(" -> ")));
+        }
+        workflow.include_chain = include_chain;
+        workflow.base_url = workflow.base_url.or_else(|| self.base_url.clone());
+        workflow.headers = merge_headers(self.headers.clone(), workflow.headers);
+        workflow.authentication = workflow
+            .authentication
+            .or_else(|| self.authentication.clone());
+        workflow.success = workflow.success.or_else(|| self.success.clone());
+        workflow.tag_filter = self.tag_filter.clone();
+        workflow.junit = self.junit.clone();
+        workflow.fixtures = self.fixtures.clone();
+        workflow.cache = self.cache.clone();
+        workflow.client_timeouts = self.client_timeouts;
+        workflow.show_progress = self.show_progress;
+        workflow.tokens = self.tokens.clone();
+        workflow.interrupt = self.interrupt.clone();
+        Ok(workflow)
+    }
+
+    /// the requests selected by the tag filter, with referenced files and
+    /// included workflows loaded
+    fn all_requests(&self) -> anyhow::Result<Vec<Request>> {
+        let mut requests = Vec::new();
+        for step in &self.requests {
+            match step {
+                Step::Include { include } => {
+                    requests.extend(self.include(include)?.all_requests()?)
+                }
+                step => requests.extend(self.step_request(step)?.map(Cow::into_owned)),
+            }
+        }
+        requests.retain(|request| self.tag_filter.selects(request));
+        Ok(requests)
+    }
+
+    /// the request of a step with the workflow defaults applied
+    fn step_request<'s>(&self, step: &'s Step) -> anyhow::Result<Option<Cow<'s, Request>>> {
+        let Some(request) = step.request(&self.base_dir)? else {
+            return Ok(None);
+        };
+        if self.base_url.is_none()
+            && self.headers.is_none()
+            && self.authentication.is_none()
+            && self.fixtures.is_none()
+            && self.cache.is_none()
+            && self.client_timeouts == ClientTimeouts::default()
+            && !self.show_progress
+            && self.tokens.is_none()
+        {
+            return Ok(Some(request));
+        }
+
+        let mut request = request.into_owned();
+        if let Some(base_url) = &self.base_url {
+            if request.uri.starts_with('/') {
+                request.uri = format!("{}{}", base_url.trim_end_matches('/'), request.uri);
+            }
+        }
+        request.headers = merge_headers(self.headers.clone(), request.headers.take());
+        if request.authentication.is_none() {
+            request.authentication = self.authentication.clone();
+        }
+        request.fixtures = self.fixtures.clone();
+        request.cache = self.cache.clone();
+        request.client_timeouts = self.client_timeouts;
+        request.show_progress = self.show_progress;
+        request.tokens = self.tokens.clone();
+        Ok(Some(Cow::Owned(request)))
+    }
+
+    /// whether Ctrl-C was pressed, the remaining steps are then skipped
+    fn is_interrupted(&self) -> bool {
+        let interrupted = self.interrupt.as_ref().is_some_and(Interrupt::is_set);
+        if interrupted {
+            warn!("interrupted, skipping the remaining steps");
+        }
+        interrupted
+    }
+
+    /// completes once Ctrl-C is pressed, never without an interrupt handler
+    async fn interruption(&self) {
+        match &self.interrupt {
+            Some(interrupt) => interrupt.wait().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// records the outcome of a step when a junit report is requested
+    fn report(&self, record: impl FnOnce(&JunitReport)) {
+        if let Some(junit) = &self.junit {
+            record(junit);
+        }
+    }
+
+    /// the files the workflow reads besides its own: referenced request files,
+    /// included workflows and request bodies
+    pub fn files(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for step in &self.requests {
+            match step {
+                Step::Include { include } => {
+                    files.push(self.base_dir.join(include));
+                    files.extend(self.include(include)?.files()?);
+                }
+                Step::Reference { reference } => files.push(self.base_dir.join(reference)),
+                Step::Request(_) => {}
+            }
+            if let Some(request) = step.request(&self.base_dir)? {
+                files.extend(request.files());
+            }
+        }
+        Ok(files)
+    }
+
+    pub fn redirect_policy(&self) -> reqwest::redirect::Policy {
+        redirect_policy(self.follow_redirects, self.max_redirects)
+    }
+
+    pub fn identity(
+        &self,
+        templates: &Templates,
+        variables: &HashMap<String, Option<String>>,
+    ) -> anyhow::Result<Option<reqwest::Identity>> {
+        self.client_certificate
+            .as_ref()
+            .map(|certificate| certificate.identity(templates, variables))
+            .transpose()
+    }
+
+    /// variables read by the requests that are neither extracted by a step nor
+    /// set by the requests themselves, they have to be provided upfront
+    pub fn referenced_variables(&self, templates: &Templates) -> anyhow::Result<BTreeSet<String>> {
+        let requests = self.all_requests()?;
+        let mut variables: BTreeSet<String> = requests
+            .iter()
+            .flat_map(|request| request.referenced_variables(templates))
+            .collect();
+        variables.remove(PREVIOUS_BODY);
+        variables.remove(PREVIOUS_STATUS);
+        for request in &requests {
+            for name in request.extractors.iter().flatten().map(|(name, _)| name) {
+                variables.remove(name);
+            }
+            variables.remove(&step_variable(&request.name, "time_ms"));
+            variables.remove(&step_variable(&request.name, "ttfb_ms"));
+            if request.paginate.is_some() {
+                variables.remove("pages");
+            }
+        }
+        Ok(variables)
+    }
+
+    /// number of requests, starting at `start`, that can be executed together.
+    /// Requests with extractors always run on their own, so that the variables
+    /// they extract are available to the following requests. A batch also ends
+    /// before a request reading a variable set by an earlier request of the
+    /// batch: `previous_body`, `previous_status`, its times or `pages`.
+    fn batch_len(
+        &self,
+        requests: &[Cow<'_, Request>],
+        start: usize,
+        templates: &Templates,
+    ) -> usize {
+        let independent = |request: &Request| {
+            request
+                .extractors
+                .iter()
+                .all(|extractors| extractors.is_empty())
+        };
+
+        if self.concurrency.unwrap_or(1) <= 1 || !independent(&requests[start]) {
+            return 1;
+        }
+        let mut len = 1;
+        for request in &requests[start + 1..] {
+            if !independent(request) {
+                break;
+            }
+            let referenced = request.referenced_variables(templates);
+            let depends = referenced.contains(PREVIOUS_BODY)
+                || referenced.contains(PREVIOUS_STATUS)
+                || requests[start..start + len].iter().any(|earlier| {
+                    referenced.contains(&step_variable(&earlier.name, "time_ms"))
+                        || referenced.contains(&step_variable(&earlier.name, "ttfb_ms"))
+                        || (earlier.paginate.is_some() && referenced.contains("pages"))
+                });
+            if depends {
+                break;
+            }
+            len += 1;
+        }
+        len
+    }
+
+    /// loads the referenced and included files and checks the requests and
+    /// the files they read, without sending any of them
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for request in self.all_requests()? {
+            request.validate()?;
+        }
+        Ok(())
+    }
+
+    /// checks the requests before any of them is sent and sets up the progress
+    /// of the run
+    fn prepare(&self) -> anyhow::Result<Progress> {
+        let requests = self.all_requests()?;
+        for request in &requests {
+            request.validate_extractors()?;
+        }
+        Ok(Progress::new(requests.len()))
+    }
+
     pub async fn execute<T: IntoIterator<Item = (String, Option<String>)>>(
         &self,
         client: &Client,
+        templates: &Templates,
         variables: T,
         files: Option<Vec<PathBuf>>,
         request_action: &impl Fn(&Request, &RequestContext),
@@ -47,48 +715,281 @@ impl Workflow {
     ) -> anyhow::Result<WorkflowResult> {
         debug!("executing workflow: {:?}", self.name);
 
-        let mut additional_variables: HashMap<String, Option<String>> = HashMap::new();
-
-        for file in files.unwrap_or_default() {
-            let filename = file.display().to_string();
-            debug!("loading context from file: {}", filename);
-            let file_variables: HashMap<String, Option<String>> = match tokio::fs::read(file).await
-            {
-                Ok(context_content) => serde_yaml::from_slice(&context_content)?,
-                Err(e) => {
-                    return Err(anyhow::anyhow!(
-                        "failed to read context file {}: {}",
-                        filename,
-                        e
-                    ))
-                }
-            };
-            additional_variables.extend(file_variables);
-        }
+        // file variables override the initial ones and are overridden by the
+        // variables extracted by the steps
+        let additional_variables = load_variable_files(files.unwrap_or_default()).await?;
+        let variables = variables.into_iter().chain(additional_variables);
+        self.run(
+            WorkflowContext::new(variables),
+            client,
+            templates,
+            false,
+            &self.prepare()?,
+            request_action,
+            response_action,
+        )
+        .await
+    }
+
+    /// goes through the workflow without sending any request, see [`Request::dry_run`].
+    /// Steps are executed one at a time and without delays.
+    pub async fn dry_run<T: IntoIterator<Item = (String, Option<String>)>>(
+        &self,
+        client: &Client,
+        templates: &Templates,
+        variables: T,
+        files: Option<Vec<PathBuf>>,
+        request_action: &impl Fn(&Request, &RequestContext),
+    ) -> anyhow::Result<WorkflowResult> {
+        debug!("dry running workflow: {:?}", self.name);
 
+        let additional_variables = load_variable_files(files.unwrap_or_default()).await?;
         let variables = variables.into_iter().chain(additional_variables);
+        self.run(
+            WorkflowContext::new(variables),
+            client,
+            templates,
+            true,
+            &self.prepare()?,
+            request_action,
+            &|_, _, _| (),
+        )
+        .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn run<'a, Q, S>(
+        &'a self,
+        mut context: WorkflowContext,
+        client: &'a Client,
+        templates: &'a Templates,
+        dry_run: bool,
+        progress: &'a Progress,
+        request_action: &'a Q,
+        response_action: &'a S,
+    ) -> LocalBoxFuture<'a, anyhow::Result<WorkflowResult>>
+    where
+        Q: Fn(&Request, &RequestContext),
+        S: Fn(&Request, &RequestContext, &Response),
+    {
+        async move {
+            let mut responses: Vec<Response> = Vec::new();
+            let mut failed_steps: Vec<String> = Vec::new();
+            let mut passed_steps: Vec<String> = Vec::new();
+            let mut interrupted = false;
+
+            let concurrency = if dry_run {
+                1
+            } else {
+                self.concurrency.unwrap_or(1).max(1)
+            };
+            let mut index = 0;
+            'steps: while index < self.requests.len() {
+                if self.is_interrupted() {
+                    interrupted = true;
+                    break;
+                }
+                if let Step::Include { include } = &self.requests[index] {
+                    index += 1;
+                    let workflow = self.include(include)?;
+                    info!("including workflow {}", workflow.name);
+                    let result = workflow
+                        .run(
+                            context.clone(),
+                            client,
+                            templates,
+                            dry_run,
+                            progress,
+                            request_action,
+                            response_action,
+                        )
+                        .await?;
+                    context.update(result.final_variables);
+                    responses.extend(result.responses);
+                    failed_steps.extend(result.failed_steps);
+                    passed_steps.extend(result.passed_steps);
+                    if result.interrupted {
+                        interrupted = true;
+                        break;
+                    }
+                    continue;
+                }
+
+                // the requests up to the next include
+                let steps = self.requests[index..]
+                    .iter()
+                    .take_while(|step| !matches!(step, Step::Include { .. }));
+                let first_index = index;
+                index += steps.clone().count();
+                let mut requests = steps
+                    .filter_map(|step| self.step_request(step).transpose())
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                requests.retain(|request| {
+                    let selected = self.tag_filter.selects(request);
+                    if !selected {
+                        info!("skipping request {}, not selected by tags", request.name);
+                    }
+                    selected
+                });
+
+                let mut start = 0;
+                while start < requests.len() {
+                    if self.is_interrupted() {
+                        interrupted = true;
+                        break 'steps;
+                    }
+                    let batch =
+                        &requests[start..start + self.batch_len(&requests, start, templates)];
+                    let first_step = first_index + start == 0;
+                    start += batch.len();
+
+                    let steps: Vec<_> = batch.iter().map(|_| progress.next()).collect();
+                    let results = stream::iter(batch.iter().zip(&steps).map(|(request, step)| {
+                        let delay = match &request.delay {
+                            Some(delay) => Some(delay),
+                            None if !first_step => self.delay.as_ref(),
+                            None => None,
+                        };
+                        execute_step(
+                            request,
+                            *step,
+                            delay,
+                            &context,
+                            client,
+                            templates,
+                            dry_run,
+                            request_action,
+                            response_action,
+                        )
+                    }))
+                    .buffered(concurrency);
+                    // Ctrl-C cancels the steps in flight and skips the remaining ones
+                    let results = tokio::select! {
+                        results = results.collect::<Vec<_>>() => results,
+                        () = self.interruption() => {
+                            warn!("interrupted, skipping the remaining steps");
+                            interrupted = true;
+                            break 'steps;
+                        }
+                    };
 
-        let mut context: WorkflowContext = WorkflowContext::new(variables);
-        let mut responses: Vec<Response> = Vec::new();
+                    for ((request, step), result) in batch.iter().zip(steps).zip(results) {
+                        let continue_on_error = request.continue_on_error.unwrap_or(false);
 
-        for request in &self.requests {
-            debug!("executing request: {:?}", request);
+                        if let Err(err) = &result {
+                            log_outcome(request, step, "error");
+                            self.report(|junit| {
+                                junit.error(&self.name, &request.name, err.to_string())
+                            });
+                        }
+                        let step_responses = match result {
+                            Ok(responses) if responses.is_empty() => {
+                                log_outcome(request, step, "skipped");
+                                self.report(|junit| junit.skipped(&self.name, &request.name));
+                                continue;
+                            }
+                            Ok(responses) => responses,
+                            Err(err) if continue_on_error => {
+                                error!("request {} failed, continuing: {:?}", request.name, err);
+                                failed_steps.push(request.name.clone());
+                                continue;
+                            }
+                            Err(err) => return Err(err),
+                        };
 
-            let response = request
-                .execute(client, &context.variables, request_action, response_action)
-                .await?;
+                        let mut step_failed = false;
+                        for response in step_responses {
+                            if dry_run {
+                                context.update(response.extracted_variables);
+                                continue;
+                            }
 
-            if !StatusCode::from_u16(response.status_code)?.is_success() {
-                return Err(anyhow::anyhow!("request failed: {:?}", response));
+                            let expected = request.expect_status.as_ref().or(self.success.as_ref());
+                            let status_error =
+                                if !is_expected_status(expected, response.status_code) {
+                                    Some(match expected {
+                                        Some(expected) => format!(
+                                            "request {} failed: expected status {}, got {}",
+                                            request.name, expected, response.status_code
+                                        ),
+                                        None => format!("request failed: {:?}", response),
+                                    })
+                                } else if !response.assertions.failures.is_empty() {
+                                    Some(format!(
+                                        "request {} failed: {} assertion(s) failed: {}",
+                                        request.name,
+                                        response.assertions.failures.len(),
+                                        response.assertions.failures.join("; ")
+                                    ))
+                                } else {
+                                    None
+                                };
+
+                            match &status_error {
+                                Some(status_error) => self.report(|junit| {
+                                    junit.failed(
+                                        &self.name,
+                                        &request.name,
+                                        response.time_total,
+                                        status_error.clone(),
+                                    )
+                                }),
+                                None => self.report(|junit| {
+                                    junit.passed(&self.name, &request.name, response.time_total)
+                                }),
+                            }
+                            if let Some(status_error) = status_error {
+                                if !continue_on_error {
+                                    log_outcome(request, step, "failed");
+                                    return Err(UnexpectedResponse(status_error).into());
+                                }
+                                error!("{}, continuing", status_error);
+                                if !step_failed {
+                                    failed_steps.push(request.name.clone());
+                                }
+                                step_failed = true;
+                            }
+
+                            context.update(response.extracted_variables.clone());
+                            context.update([
+                                (PREVIOUS_BODY.to_string(), Some(response.body.clone())),
+                                (
+                                    PREVIOUS_STATUS.to_string(),
+                                    Some(response.status_code.to_string()),
+                                ),
+                                (
+                                    step_variable(&request.name, "time_ms"),
+                                    Some(response.time_total.as_millis().to_string()),
+                                ),
+                                (
+                                    step_variable(&request.name, "ttfb_ms"),
+                                    Some(response.time_to_headers.as_millis().to_string()),
+                                ),
+                            ]);
+                            responses.push(response);
+                        }
+                        if step_failed {
+                            log_outcome(request, step, "failed");
+                        } else {
+                            log_outcome(request, step, "passed");
+                            passed_steps.push(request.name.clone());
+                        }
+                    }
+                }
             }
 
-            context.update(response.extracted_variables.clone());
-            responses.push(response);
+            // the last response is not a variable of the run
+            let mut final_variables = context.variables;
+            final_variables.remove(PREVIOUS_BODY);
+            final_variables.remove(PREVIOUS_STATUS);
+            Ok(WorkflowResult {
+                responses,
+                final_variables,
+                failed_steps,
+                passed_steps,
+                interrupted,
+            })
         }
-
-        Ok(WorkflowResult {
-            responses,
-            final_variables: context.variables,
-        })
+        .boxed_local()
     }
 }
@@ -1,19 +1,102 @@
-use clap::{Parser, Subcommand};
-use log::{error, LevelFilter};
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use log::{error, warn, LevelFilter};
 use schemars::schema_for;
 
-use std::borrow::Borrow;
-use std::path::PathBuf;
+use indexmap::IndexMap;
+use notify::{RecursiveMode, Watcher};
+use std::borrow::{Borrow, Cow};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
 use std::{collections::HashMap, env, fs::File, process::ExitCode};
+use termimad::crossterm::{
+    cursor::MoveTo,
+    execute,
+    terminal::{Clear, ClearType},
+};
 use termimad::minimad::TextTemplate;
 use termimad::MadSkin;
 
 mod climan;
-use climan::request::{Request, RequestContext, Response};
-use climan::workflow::Workflow;
+use climan::cache::ResponseCache;
+use climan::cookies::CookieJar;
+use climan::data;
+use climan::dns::TimedResolver;
+use climan::fixtures::Fixtures;
+use climan::har::HarRecorder;
+use climan::import;
+use climan::interrupt::{Interrupt, EXIT_INTERRUPTED};
+use climan::json_log::JsonLogger;
+use climan::model::{Authentication, Body, DurationValue, HeaderValue};
+use climan::repeat::{self, RepeatStats};
+use climan::request::{ClientTimeouts, Request, RequestContext, Response, UnexpectedResponse};
+use climan::spec;
+use climan::template::Templates;
+use climan::workflow::{load_variable_files, TagFilter, Workflow, WorkflowResult, PREVIOUS_BODY};
 
+const REDACTED: &str = "••••";
+/// spec path that reads the spec from stdin
+const STDIN_PATH: &str = "-";
+
+/// header and variable values that are masked in the terminal output
+#[derive(Clone, Default)]
+struct Redaction {
+    headers: Vec<String>,
+    secrets: Vec<String>,
+}
+
+impl Redaction {
+    fn header<'v>(&self, name: &str, value: &'v str) -> &'v str {
+        if self
+            .headers
+            .iter()
+            .any(|header| header.eq_ignore_ascii_case(name))
+        {
+            REDACTED
+        } else {
+            value
+        }
+    }
+
+    fn variable<'v>(&self, name: &str, value: &'v str) -> &'v str {
+        if self.secrets.iter().any(|secret| secret == name) {
+            REDACTED
+        } else {
+            value
+        }
+    }
+
+    /// masks the values of the secret variables wherever they appear in `text`
+    fn text(&self, text: &str, variables: &HashMap<String, Option<String>>) -> String {
+        self.secrets
+            .iter()
+            .filter_map(|secret| variables.get(secret).cloned().flatten())
+            .filter(|value| !value.is_empty())
+            .fold(text.to_string(), |text, value| {
+                text.replace(&value, REDACTED)
+            })
+    }
+}
+
+/// shortens a value to `width` characters, ending it with an ellipsis
+fn truncate(value: &str, width: Option<usize>) -> Cow<'_, str> {
+    match width {
+        Some(width) if value.chars().count() > width => {
+            let kept: String = value.chars().take(width.saturating_sub(1)).collect();
+            Cow::Owned(format!("{kept}…"))
+        }
+        _ => Cow::Borrowed(value),
+    }
+}
+
+/// prints the headers, values longer than `width` characters are truncated
 fn print_header_table<'v, T: IntoIterator<Item = (&'v str, &'v str)>>(
     skin: &MadSkin,
+    redaction: &Redaction,
+    width: Option<usize>,
     header_map: T,
 ) {
     let template = TextTemplate::from(
@@ -28,15 +111,23 @@ fn print_header_table<'v, T: IntoIterator<Item = (&'v str, &'v str)>>(
     "#,
     );
 
+    let values: Vec<(&str, Cow<str>)> = header_map
+        .into_iter()
+        .map(|(name, value)| (name, truncate(redaction.header(name, value), width)))
+        .collect();
     let mut expander = template.expander();
-    for (name, value) in header_map {
+    for (name, value) in &values {
         expander.sub("rows").set("name", name).set("value", value);
     }
 
     skin.print_expander(expander);
 }
 
-fn print_variable_table(skin: &MadSkin, variables: &HashMap<String, Option<String>>) {
+fn print_variable_table(
+    skin: &MadSkin,
+    redaction: &Redaction,
+    variables: &HashMap<String, Option<String>>,
+) {
     let template = TextTemplate::from(
         r#"
     | :-: | :-: |
@@ -52,21 +143,43 @@ fn print_variable_table(skin: &MadSkin, variables: &HashMap<String, Option<Strin
     let mut expander = template.expander();
     for (name, value) in variables {
         let value = value.as_ref().map(|v| v.as_str()).unwrap_or("");
-        expander.sub("rows").set("name", name).set("value", value);
+        expander
+            .sub("rows")
+            .set("name", name)
+            .set("value", redaction.variable(name, value));
     }
 
     skin.print_expander(expander);
 }
 
-fn on_request(skin: MadSkin, request: &Request, context: &RequestContext) {
-    let step_template = TextTemplate::from("# 📗 Executing step: ${name}");
+fn on_request(
+    skin: MadSkin,
+    redaction: &Redaction,
+    header_width: Option<usize>,
+    request: &Request,
+    context: &RequestContext,
+) {
+    let step_template = TextTemplate::from("# 📗 Executing step${position}: ${name}");
     let mut step_expander = step_template.expander();
-    step_expander.set("name", &request.name);
+    let position = context
+        .step
+        .map(|(step, total)| format!(" {step}/{total}"))
+        .unwrap_or_default();
+    step_expander
+        .set("position", &position)
+        .set("name", &request.name);
 
     skin.print_expander(step_expander);
 
+    // the previous body is already shown with the previous response
+    let variables: HashMap<String, Option<String>> = context
+        .variables
+        .iter()
+        .filter(|(name, _)| name.as_str() != PREVIOUS_BODY)
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
     skin.print_text("* **Variables:**");
-    print_variable_table(&skin, context.variables);
+    print_variable_table(&skin, redaction, &variables);
     println!();
 
     let template = TextTemplate::from(
@@ -77,39 +190,57 @@ fn on_request(skin: MadSkin, request: &Request, context: &RequestContext) {
     );
     let mut expander = template.expander();
     let method_name = context.method.to_string();
+    let url = redaction.text(&context.uri, context.variables);
     expander
         .set("name", &request.name)
         .set("method", &method_name)
-        .set("url", &context.uri);
+        .set("url", &url);
     skin.print_expander(expander);
 
     skin.print_text("* **Headers:**");
+    let headers: Vec<(&str, String)> = context
+        .headers
+        .iter()
+        .map(|(k, v)| (k.as_str(), redaction.text(v, context.variables)))
+        .collect();
     print_header_table(
         &skin,
-        context
-            .headers
-            .borrow()
-            .iter()
-            .map(|(k, v)| (k.as_str(), v.as_str())),
+        redaction,
+        header_width,
+        headers.iter().map(|(k, v)| (*k, v.as_str())),
     );
 
     skin.print_text("* **Body:**");
     let body_template = TextTemplate::from("```\n${body}\n```");
     let mut body_expander = body_template.expander();
-    let body_content = context.body.as_deref().unwrap_or("");
-    body_expander.set_lines("body", body_content);
+    let body_content = redaction.text(context.body.as_deref().unwrap_or(""), context.variables);
+    body_expander.set_lines("body", &body_content);
     skin.print_expander(body_expander);
     println!();
 }
 
-fn on_response(skin: MadSkin, _request: &Request, _context: &RequestContext, response: &Response) {
-    let template = TextTemplate::from(
+fn on_response(
+    skin: MadSkin,
+    redaction: &Redaction,
+    header_width: Option<usize>,
+    _request: &Request,
+    context: &RequestContext,
+    response: &Response,
+) {
+    // the lookup time is only known for new connections to host names
+    let dns_line = match response.dns {
+        Some(_) => "\n* **DNS lookup:** ${dns}ms",
+        None => "",
+    };
+    let template_text = format!(
         r#"
 ## 📥 Response properties
-* **Status**: ${status_color} ${status_code}
-* **Time to Headers:** ${time_to_headers}ms
-* **Time total:** ${time_total}ms"#,
+* **Status**: ${{status_color}} ${{status_code}}
+* **Protocol:** ${{protocol}}{dns_line}
+* **Time to Headers:** ${{time_to_headers}}ms
+* **Time total:** ${{time_total}}ms"#
     );
+    let template = TextTemplate::from(template_text.as_str());
     let mut expander = template.expander();
 
     let status_color = match response.status_code {
@@ -119,21 +250,54 @@ fn on_response(skin: MadSkin, _request: &Request, _context: &RequestContext, res
         500..=599 => "🔥",
         _ => "",
     };
-    let status_code = response.status_code.to_string();
+    let status_code = if response.not_modified {
+        format!("{} (304 Not Modified, cached body)", response.status_code)
+    } else {
+        response.status_code.to_string()
+    };
+    let protocol = if response.tls {
+        format!("{} over TLS", response.http_version)
+    } else {
+        response.http_version.clone()
+    };
+    let dns = response
+        .dns
+        .map(|dns| dns.as_millis().to_string())
+        .unwrap_or_default();
     let time_to_headers = response.time_to_headers.as_millis().to_string();
     let time_total = response.time_total.as_millis().to_string();
 
     expander
+        .set("dns", &dns)
         .set("status_color", status_color)
         .set("status_code", &status_code)
+        .set("protocol", &protocol)
         .set("time_to_headers", &time_to_headers)
         .set("time_total", &time_total);
 
     skin.print_expander(expander);
 
+    let assertions = &response.assertions;
+    if assertions.passed + assertions.failures.len() > 0 {
+        let assertions_template =
+            TextTemplate::from("* **Assertions:** ✅ ${passed} passed, ❌ ${failed} failed");
+        let mut assertions_expander = assertions_template.expander();
+        let passed = assertions.passed.to_string();
+        let failed = assertions.failures.len().to_string();
+        assertions_expander
+            .set("passed", &passed)
+            .set("failed", &failed);
+        skin.print_expander(assertions_expander);
+        for failure in &assertions.failures {
+            skin.print_text(&format!("  * ❌ `{failure}`"));
+        }
+    }
+
     skin.print_text("* **Headers:**");
     print_header_table(
         &skin,
+        redaction,
+        header_width,
         response
             .headers
             .borrow()
@@ -142,17 +306,313 @@ fn on_response(skin: MadSkin, _request: &Request, _context: &RequestContext, res
     );
 
     skin.print_text("* **Extracted variables:**");
-    print_variable_table(&skin, &response.extracted_variables);
+    print_variable_table(&skin, redaction, &response.extracted_variables);
+
+    if let Some(saved_to) = &response.saved_to {
+        let saved_template = TextTemplate::from("* **Body:** ${size} bytes saved to `${path}`");
+        let mut saved_expander = saved_template.expander();
+        let size = response.body_size.to_string();
+        saved_expander.set("size", &size).set("path", saved_to);
+        skin.print_expander(saved_expander);
+        println!();
+        return;
+    }
 
     skin.print_text("* **Body:**");
     let body_template = TextTemplate::from("```\n${body}\n```");
     let mut body_expander = body_template.expander();
-    body_expander.set_lines("body", &response.body);
+    let body_content = redaction.text(&response.pretty_body(), context.variables);
+    body_expander.set_lines("body", &body_content);
     skin.print_expander(body_expander);
     println!();
 }
 
-#[derive(Parser, Debug)]
+/// single line description of a request and its response, used in quiet mode
+fn summary_line(
+    redaction: &Redaction,
+    context: &RequestContext,
+    response: Option<&Response>,
+) -> String {
+    let request = format!(
+        "{}{} {}",
+        context
+            .step
+            .map(|(step, total)| format!("[{step}/{total}] "))
+            .unwrap_or_default(),
+        context.method.to_string().to_uppercase(),
+        redaction.text(&context.url(), context.variables)
+    );
+    match response {
+        Some(response) => format!(
+            "{request} -> {} ({}ms)",
+            response.status_code,
+            response.time_total.as_millis()
+        ),
+        None => request,
+    }
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// renders the resolved request as an equivalent curl command line
+fn curl_command(request: &Request, context: &RequestContext) -> String {
+    let mut args = vec![
+        "curl".to_string(),
+        "-X".to_string(),
+        context.method.to_string().to_uppercase(),
+        shell_quote(&context.url()),
+    ];
+
+    // curl picks its own multipart boundary
+    let multipart = matches!(request.body, Some(Body::Multipart { .. }));
+    for (name, value) in &context.headers {
+        if multipart && name.eq_ignore_ascii_case("content-type") {
+            continue;
+        }
+        args.push("-H".to_string());
+        args.push(shell_quote(&format!("{name}: {value}")));
+    }
+
+    match &context.authentication {
+        Some(Authentication::Basic { username, password }) => {
+            let credentials = match password {
+                Some(password) => format!("{username}:{password}"),
+                None => username.clone(),
+            };
+            args.push("-u".to_string());
+            args.push(shell_quote(&credentials));
+        }
+        Some(Authentication::Bearer { token }) => {
+            args.push("-H".to_string());
+            args.push(shell_quote(&format!("Authorization: Bearer {token}")));
+        }
+        Some(Authentication::AwsSigV4 {
+            access_key,
+            secret_key,
+            region,
+            service,
+            session_token,
+        }) => {
+            args.push("--aws-sigv4".to_string());
+            args.push(shell_quote(&format!("aws:amz:{region}:{service}")));
+            args.push("-u".to_string());
+            args.push(shell_quote(&format!("{access_key}:{secret_key}")));
+            if let Some(session_token) = session_token {
+                args.push("-H".to_string());
+                args.push(shell_quote(&format!(
+                    "X-Amz-Security-Token: {session_token}"
+                )));
+            }
+        }
+        // api keys are part of the headers or query parameters, oauth2 is
+        // resolved to a bearer token before the request is sent
+        Some(Authentication::ApiKey { .. }) | Some(Authentication::OAuth2 { .. }) | None => {}
+    }
+
+    match (&request.body, &context.body) {
+        (Some(Body::Multipart { .. }), Some(body)) => {
+            for field in body.lines() {
+                args.push("-F".to_string());
+                args.push(shell_quote(field));
+            }
+        }
+        (_, Some(body)) => {
+            args.push("--data-raw".to_string());
+            args.push(shell_quote(body));
+        }
+        (_, None) => {}
+    }
+
+    args.join(" ")
+}
+
+/// the outcome of a workflow run, printed in json mode and written with
+/// --summary. The secret variables are masked.
+fn workflow_summary(
+    result: &WorkflowResult,
+    redaction: &Redaction,
+    duration: Duration,
+) -> serde_json::Value {
+    let final_variables: HashMap<&String, Option<&str>> = result
+        .final_variables
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .as_deref()
+                .map(|value| redaction.variable(name, value));
+            (name, value)
+        })
+        .collect();
+    serde_json::json!({
+        "total": result.passed_steps.len() + result.failed_steps.len(),
+        "passed": result.passed_steps.len(),
+        "failed": result.failed_steps.len(),
+        "duration_ms": duration.as_millis(),
+        "final_variables": final_variables,
+        "failed_steps": result.failed_steps,
+        "passed_steps": result.passed_steps,
+        "interrupted": result.interrupted,
+    })
+}
+
+fn print_workflow_summary(
+    skin: &MadSkin,
+    redaction: &Redaction,
+    result: &WorkflowResult,
+    duration: Duration,
+) {
+    let template = TextTemplate::from(
+        r#"
+# 🏁 Summary
+| :-: | :-: | :-: | :-: |
+| **Steps** | **Passed** | **Failed** | **Duration** |
+| :-: | :-: | :-: | :-: |
+| ${total} | ✅ ${passed} | ❌ ${failed} | ${duration}ms |
+| - | - | - | - |
+"#,
+    );
+    let mut expander = template.expander();
+    let total = (result.passed_steps.len() + result.failed_steps.len()).to_string();
+    let passed = result.passed_steps.len().to_string();
+    let failed = result.failed_steps.len().to_string();
+    let duration = duration.as_millis().to_string();
+    expander
+        .set("total", &total)
+        .set("passed", &passed)
+        .set("failed", &failed)
+        .set("duration", &duration);
+    skin.print_expander(expander);
+    for step in &result.failed_steps {
+        skin.print_text(&format!("  * ❌ `{step}`"));
+    }
+
+    skin.print_text("* **Final variables:**");
+    print_variable_table(skin, redaction, &result.final_variables);
+    println!();
+}
+
+fn print_repeat_stats(output: OutputFormat, stats: &RepeatStats) {
+    let percentiles: Vec<(&str, Option<u128>)> = [("p50", 50), ("p95", 95), ("p99", 99)]
+        .into_iter()
+        .map(|(name, percentile)| {
+            let time = stats.percentile(percentile);
+            (name, time.map(|time| time.as_millis()))
+        })
+        .collect();
+
+    if let OutputFormat::Json = output {
+        let stats = serde_json::json!({
+            "sent": stats.sent,
+            "succeeded": stats.succeeded,
+            "errors": stats.errors,
+            "statuses": stats.statuses,
+            "elapsed_ms": stats.elapsed.as_millis(),
+            "time_total_ms": BTreeMap::from_iter(percentiles),
+        });
+        println!("{stats}");
+        return;
+    }
+
+    println!(
+        "{} requests in {}ms: {} succeeded, {} without response",
+        stats.sent,
+        stats.elapsed.as_millis(),
+        stats.succeeded,
+        stats.errors
+    );
+    for (status, count) in &stats.statuses {
+        println!("  status {status}: {count}");
+    }
+    if percentiles.iter().all(|(_, time)| time.is_some()) {
+        let times: Vec<String> = percentiles
+            .iter()
+            .map(|(name, time)| format!("{name} {}ms", time.unwrap_or_default()))
+            .collect();
+        println!("  time total: {}", times.join(", "));
+    }
+}
+
+fn print_json_request(redaction: &Redaction, request: &Request, context: &RequestContext) {
+    // repeated headers are listed under their name, like in the specs
+    let mut headers: IndexMap<&String, HeaderValue> = IndexMap::new();
+    for (name, value) in &context.headers {
+        let value = redaction.text(redaction.header(name, value), context.variables);
+        match headers.get_mut(name) {
+            Some(values) => values.push(value),
+            None => {
+                headers.insert(name, value.into());
+            }
+        }
+    }
+    let step = serde_json::json!({
+        "name": request.name,
+        "method": context.method.to_string(),
+        "uri": redaction.text(&context.url(), context.variables),
+        "headers": headers,
+        "body": context.body.as_deref().map(|body| redaction.text(body, context.variables)),
+    });
+    println!("{step}");
+}
+
+fn print_json_response(
+    redaction: &Redaction,
+    request: &Request,
+    context: &RequestContext,
+    response: &Response,
+) {
+    let headers: HashMap<&String, String> = response
+        .headers
+        .iter()
+        .map(|(name, value)| {
+            let value = redaction.text(redaction.header(name, value), context.variables);
+            (name, value)
+        })
+        .collect();
+    let extracted_variables: HashMap<&String, Option<&str>> = response
+        .extracted_variables
+        .iter()
+        .map(|(name, value)| {
+            (
+                name,
+                value
+                    .as_deref()
+                    .map(|value| redaction.variable(name, value)),
+            )
+        })
+        .collect();
+    let mut response_json = serde_json::to_value(response).unwrap_or_default();
+    response_json["headers"] = serde_json::json!(headers);
+    response_json["body"] = redaction.text(&response.body, context.variables).into();
+    response_json["extracted_variables"] = serde_json::json!(extracted_variables);
+    let step = serde_json::json!({
+        "name": request.name,
+        "method": context.method.to_string(),
+        "uri": redaction.text(&context.url(), context.variables),
+        "response": response_json,
+    });
+    println!("{step}");
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum OutputFormat {
+    /// rendered markdown tables, meant for humans
+    Markdown,
+    /// one JSON object per line, meant for scripts
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum LogFormat {
+    /// human readable lines
+    Text,
+    /// one JSON object per line, with the request, step and outcome of the
+    /// workflow steps as fields
+    Json,
+}
+
+#[derive(Parser, Debug, Clone)]
 #[command(version, about, long_about = None)]
 struct Cli {
     #[command(subcommand)]
@@ -160,17 +620,180 @@ struct Cli {
 
     /// set this to log the output into the .climan.log file in the current folder
     #[arg(short, long)]
-    log_file: Option<bool>,
+    log_file: bool,
+
+    /// increase the log verbosity: -v=info, -vv=debug, -vvv=trace
+    #[arg(short, action = ArgAction::Count)]
+    verbose: u8,
 
-    /// set the log verbosity level: 0=off, 1=error, 2=warn, 3=info, 4=debug, 5=trace (default: 2)
+    /// set the log verbosity level: 0=off, 1=error, 2=warn, 3=info, 4=debug, 5=trace (default: 2,
+    /// 3 with --log-format json), overrides -v
+    #[arg(long = "log-level")]
     log_level: Option<u8>,
+
+    /// set the log format, json writes an object per line to stderr and to the
+    /// log file
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// termimad skin (yaml) used instead of the builtin one
+    #[arg(long, conflicts_with = "no_style")]
+    skin: Option<PathBuf>,
+
+    /// print plain text without colors and styles, for dumb terminals and pipes
+    #[arg(long)]
+    no_style: bool,
+
+    /// set the output format, in json mode logs are written to stderr
+    #[arg(long, value_enum, default_value_t = OutputFormat::Markdown)]
+    output: OutputFormat,
+
+    /// write all executed requests and responses into this HAR file
+    #[arg(long)]
+    har: Option<PathBuf>,
+
+    /// print each request as an equivalent curl command, to stderr in json mode
+    #[arg(long)]
+    print_curl: bool,
+
+    /// fail on undefined variables and invalid templates instead of sending the request as is
+    #[arg(long)]
+    strict: bool,
+
+    /// header whose value is masked in the output, can be repeated
+    #[arg(long = "redact-header", default_values = ["authorization", "cookie", "x-api-key"])]
+    redact_headers: Vec<String>,
+
+    /// variable whose value is masked in the output, can be repeated
+    #[arg(long = "secret")]
+    secrets: Vec<String>,
+
+    /// print header and secret variable values in clear text, for debugging
+    #[arg(long)]
+    no_redact: bool,
+
+    /// truncate the header values longer than this number of characters
+    #[arg(long, default_value_t = 80, value_parser = clap::value_parser!(u16).range(2..))]
+    header_width: u16,
+
+    /// print the header values in full instead of truncating them
+    #[arg(long)]
+    full_headers: bool,
+
+    /// print a single line per request instead of the request and response details
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// resolve and print the requests without sending them, variables extracted
+    /// by the steps are replaced by `<name>` placeholders
+    #[arg(long)]
+    dry_run: bool,
+
+    /// save the responses into this directory, to be replayed with `--replay`
+    #[arg(long, conflicts_with_all = ["replay", "dry_run"])]
+    record: Option<PathBuf>,
+
+    /// answer the requests with the responses recorded into this directory
+    /// instead of sending them
+    #[arg(long, conflicts_with = "dry_run")]
+    replay: Option<PathBuf>,
+
+    /// keep the GET responses with an ETag in this directory and revalidate them
+    /// with If-None-Match, `304 Not Modified` responses reuse the kept body
+    #[arg(long)]
+    cache: Option<PathBuf>,
+
+    /// prompt for the variables referenced by the specs that are not set,
+    /// `--secret` variables are read without echo
+    #[arg(short, long)]
+    interactive: bool,
+
+    /// run again each time the spec or the files it references change
+    #[arg(long)]
+    watch: bool,
+
+    #[command(flatten)]
+    client: ClientArgs,
+}
+
+// settings applied to the http client shared by all requests
+#[derive(Args, Debug, Clone)]
+struct ClientArgs {
+    /// do not verify TLS certificates, only meant for development environments
+    #[arg(long)]
+    insecure: bool,
+
+    /// additional PEM bundle of trusted root certificates, can be repeated
+    #[arg(long = "ca-cert")]
+    ca_certs: Vec<PathBuf>,
+
+    /// load cookies from this file before running and save them back afterwards
+    #[arg(long)]
+    cookie_jar: Option<PathBuf>,
+
+    /// default timeout for each request, in seconds or as a duration like "30s",
+    /// overridden by the request `timeout`
+    #[arg(long, value_parser = parse_duration)]
+    timeout: Option<Duration>,
+
+    /// timeout for establishing connections, in seconds or as a duration like "5s"
+    #[arg(long, value_parser = parse_duration)]
+    connect_timeout: Option<Duration>,
+
+    /// timeout for resolving host names, in seconds or as a duration like "2s"
+    #[arg(long, value_parser = parse_duration)]
+    dns_timeout: Option<Duration>,
+
+    /// HTTP version to use, the negotiated one is shown with each response
+    #[arg(long, value_enum, default_value_t = HttpVersion::Auto)]
+    http_version: HttpVersion,
+
+    /// maximum number of idle connections kept open per host, 0 opens a new
+    /// connection for each request
+    #[arg(long)]
+    pool_max_idle_per_host: Option<usize>,
+
+    /// close the idle connections after this time, in seconds or as a duration
+    /// like "30s" (default: 90s)
+    #[arg(long, value_parser = parse_duration)]
+    pool_idle_timeout: Option<Duration>,
 }
 
-#[derive(Subcommand, Debug)]
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum HttpVersion {
+    /// HTTP/1.1 only
+    #[value(name = "1.1")]
+    Http1,
+    /// HTTP/2 with prior knowledge, without upgrading from HTTP/1.1
+    #[value(name = "2")]
+    Http2,
+    /// let the client and the server agree on the version
+    Auto,
+}
+
+impl ClientArgs {
+    fn timeouts(&self) -> ClientTimeouts {
+        ClientTimeouts {
+            timeout: self.timeout,
+            connect: self.connect_timeout,
+        }
+    }
+}
+
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let duration = match value.parse::<u64>() {
+        Ok(seconds) => DurationValue::Seconds(seconds),
+        Err(_) => DurationValue::Text(value.to_string()),
+    };
+    duration.duration().map_err(|err| err.to_string())
+}
+
+#[derive(Subcommand, Debug, Clone)]
 enum Command {
     /// Executes a workflow
     Workflow {
-        /// Path to the workflow file
+        /// Path to the workflow file, `-` reads it from stdin. It can reference
+        /// the variables given on the command line or by the environment
         path: String,
 
         /// Initial variables to be used in the workflow in the format name=value
@@ -181,14 +804,65 @@ enum Command {
         #[arg(short, long)]
         files: Option<Vec<PathBuf>>,
 
+        /// write the final variables to this yaml file, it can be loaded back with --files
+        #[arg(long)]
+        save_vars: Option<PathBuf>,
+
+        /// only write these variables with --save-vars, can be repeated
+        #[arg(long, requires = "save_vars")]
+        save_only: Vec<String>,
+
+        /// only run the steps with this tag, can be repeated. Later steps may
+        /// fail when they need variables extracted by the skipped ones
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+
+        /// skip the steps with this tag, can be repeated
+        #[arg(long = "skip-tag")]
+        skip_tags: Vec<String>,
+
+        /// write a JUnit XML report with a test case per step to this file
+        #[arg(long)]
+        junit: Option<PathBuf>,
+
+        /// write the summary of the run as json to this file: the number of
+        /// passed and failed steps, the duration and the final variables
+        #[arg(long)]
+        summary: Option<PathBuf>,
+
+        /// run the workflow once per record of this csv file or json array of
+        /// objects, the fields of the record override the --variables
+        #[arg(long)]
+        data: Option<PathBuf>,
+
         /// Include environment variables as initial variables
         #[arg(short, long)]
         env: bool,
+
+        /// only include the environment variables starting with this prefix,
+        /// e.g. CLIMAN_, which is removed from their names. Implies --env
+        #[arg(long)]
+        env_prefix: Option<String>,
+
+        /// .env file with additional variables, overridden by --variables and
+        /// overriding the environment variables included with --env
+        #[arg(long)]
+        env_file: Option<PathBuf>,
+
+        /// named environment whose variables are loaded from the environments file,
+        /// overridden by --variables
+        #[arg(long)]
+        env_name: Option<String>,
+
+        /// yaml file mapping environment names to their variables
+        #[arg(long, default_value = "environments.yaml")]
+        environments: PathBuf,
     },
 
     /// Executes a single request
     Request {
-        /// Path to the request file
+        /// Path to the request file, `-` reads it from stdin. It can reference
+        /// the variables given on the command line or by the environment
         path: String,
 
         /// Initial variables to be used in the request in the format name=value
@@ -198,10 +872,126 @@ enum Command {
         /// Include environment variables as initial variables
         #[arg(short, long)]
         env: bool,
+
+        /// only include the environment variables starting with this prefix,
+        /// e.g. CLIMAN_, which is removed from their names. Implies --env
+        #[arg(long)]
+        env_prefix: Option<String>,
+
+        /// .env file with additional variables, overridden by --variables and
+        /// overriding the environment variables included with --env
+        #[arg(long)]
+        env_file: Option<PathBuf>,
+
+        /// named environment whose variables are loaded from the environments file,
+        /// overridden by --variables
+        #[arg(long)]
+        env_name: Option<String>,
+
+        /// yaml file mapping environment names to their variables
+        #[arg(long, default_value = "environments.yaml")]
+        environments: PathBuf,
+
+        /// send the request this many times and print the success count,
+        /// status codes and response time percentiles instead of the responses
+        #[arg(long)]
+        repeat: Option<usize>,
+
+        /// number of repeated requests sent at the same time
+        #[arg(long, default_value_t = 1, requires = "repeat")]
+        concurrency: usize,
     },
 
     /// Prints the schema for the workflow
     Schema,
+
+    /// Checks a workflow or request spec without sending any request
+    Validate {
+        /// Path to the workflow or request file, `-` reads it from stdin
+        path: String,
+    },
+
+    /// Converts requests from other tools into climan specs
+    Import {
+        #[command(subcommand)]
+        source: ImportSource,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum ImportSource {
+    /// Converts a curl command into a request spec
+    Curl {
+        /// The curl command line, e.g. "curl -X POST https://example.com -d 'a=1'"
+        command: String,
+
+        /// File to write the spec to, the spec is printed to stdout when omitted
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Converts a postman v2.1 collection into a workflow spec
+    Postman {
+        /// Path to the exported collection
+        path: PathBuf,
+
+        /// File to write the spec to, the spec is printed to stdout when omitted
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+/// renders the variables in the path of a spec, the spec is not read yet so
+/// only the command line and environment variables are available
+fn spec_path(path: &str, variables: &HashMap<String, Option<String>>) -> anyhow::Result<String> {
+    Templates::new(true).render(path, variables)
+}
+
+/// loads a spec as a run would, specs with `requests` are workflows, and checks
+/// the requests and the files they reference
+fn validate_spec(path: &str) -> anyhow::Result<()> {
+    let content = read_spec(path)?;
+    let value: serde_yaml::Value = serde_yaml::from_str(&content)
+        .map_err(|err| anyhow::anyhow!("could not parse {}: {}", path, err))?;
+    if value.get("requests").is_some() {
+        let workflow = if path == STDIN_PATH {
+            Workflow::parse(&content)?
+        } else {
+            Workflow::load(Path::new(path))?
+        };
+        workflow.validate()
+    } else {
+        let mut request: Request = spec::from_yaml(&content)
+            .map_err(|err| anyhow::anyhow!("could not parse request {}: {}", path, err))?;
+        request.base_dir = Path::new(path)
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        request.validate()
+    }
+}
+
+/// reads a spec file, `-` reads it from stdin
+fn read_spec(path: &str) -> anyhow::Result<String> {
+    if path == STDIN_PATH {
+        let mut content = String::new();
+        std::io::stdin()
+            .read_to_string(&mut content)
+            .map_err(|err| anyhow::anyhow!("could not read stdin: {}", err))?;
+        return Ok(content);
+    }
+    std::fs::read_to_string(path).map_err(|err| anyhow::anyhow!("could not read {}: {}", path, err))
+}
+
+fn write_spec(spec: &str, output: Option<PathBuf>) -> anyhow::Result<()> {
+    match output {
+        Some(path) => std::fs::write(&path, spec)
+            .map_err(|err| anyhow::anyhow!("could not write {}: {}", path.display(), err)),
+        None => {
+            print!("{spec}");
+            Ok(())
+        }
+    }
 }
 
 fn parse_variables(variables: Vec<String>) -> HashMap<String, Option<String>> {
@@ -220,21 +1010,212 @@ fn parse_variables(variables: Vec<String>) -> HashMap<String, Option<String>> {
         .collect()
 }
 
-fn init_variables(variables: Option<Vec<String>>, env: bool) -> HashMap<String, Option<String>> {
-    let mut all_vars = variables.map_or(HashMap::new(), parse_variables);
-    if env {
+fn build_client(
+    args: &ClientArgs,
+    redirect_policy: reqwest::redirect::Policy,
+    identity: Option<reqwest::Identity>,
+    cookie_jar: Arc<CookieJar>,
+) -> anyhow::Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
+        .redirect(redirect_policy)
+        .cookie_provider(cookie_jar)
+        .gzip(true)
+        .brotli(true)
+        .deflate(true)
+        .tls_info(true)
+        .dns_resolver(Arc::new(TimedResolver {
+            timeout: args.dns_timeout,
+        }));
+    if let Some(timeout) = args.timeout {
+        builder = builder.timeout(timeout);
+    }
+    if let Some(connect_timeout) = args.connect_timeout {
+        builder = builder.connect_timeout(connect_timeout);
+    }
+    if let Some(max_idle) = args.pool_max_idle_per_host {
+        builder = builder.pool_max_idle_per_host(max_idle);
+    }
+    if let Some(idle_timeout) = args.pool_idle_timeout {
+        builder = builder.pool_idle_timeout(idle_timeout);
+    }
+    builder = match args.http_version {
+        HttpVersion::Http1 => builder.http1_only(),
+        HttpVersion::Http2 => builder.http2_prior_knowledge(),
+        HttpVersion::Auto => builder,
+    };
+    if args.insecure {
+        warn!("TLS certificate verification is disabled, do not use --insecure in production");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    for path in &args.ca_certs {
+        let pem = std::fs::read(path).map_err(|err| {
+            anyhow::anyhow!("could not read CA certificate {}: {}", path.display(), err)
+        })?;
+        let certificates = reqwest::Certificate::from_pem_bundle(&pem).map_err(|err| {
+            anyhow::anyhow!("could not load CA certificate {}: {}", path.display(), err)
+        })?;
+        if certificates.is_empty() {
+            anyhow::bail!("no certificates found in {}", path.display());
+        }
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    if let Some(identity) = identity {
+        builder = builder.identity(identity);
+    }
+    Ok(builder.build()?)
+}
+
+/// builds the initial variables, in increasing order of precedence: process
+/// environment (with --env), .env file, named environment, explicit --variables.
+/// Workflows then add the --files variables, the --data record and the extracted
+/// ones on top.
+fn init_variables(
+    variables: Option<Vec<String>>,
+    env: bool,
+    env_prefix: Option<String>,
+    env_file: Option<PathBuf>,
+    env_name: Option<String>,
+    environments: PathBuf,
+) -> anyhow::Result<HashMap<String, Option<String>>> {
+    let mut all_vars: HashMap<String, Option<String>> = HashMap::new();
+    if env || env_prefix.is_some() {
+        let prefix = env_prefix.unwrap_or_default();
         for (key, value) in env::vars() {
+            if let Some(key) = key.strip_prefix(&prefix) {
+                all_vars.insert(key.to_string(), Some(value));
+            }
+        }
+    }
+    if let Some(env_file) = env_file {
+        let entries = dotenvy::from_path_iter(&env_file)
+            .map_err(|err| anyhow::anyhow!("could not read {}: {}", env_file.display(), err))?;
+        for entry in entries {
+            let (key, value) = entry?;
             all_vars.insert(key, Some(value));
         }
     }
-    all_vars
+    if let Some(env_name) = env_name {
+        let content = std::fs::read_to_string(&environments)
+            .map_err(|err| anyhow::anyhow!("could not read {}: {}", environments.display(), err))?;
+        let mut all_environments: HashMap<String, HashMap<String, Option<String>>> =
+            serde_yaml::from_str(&content).map_err(|err| {
+                anyhow::anyhow!("could not parse {}: {}", environments.display(), err)
+            })?;
+        let environment = all_environments.remove(&env_name).ok_or_else(|| {
+            anyhow::anyhow!(
+                "environment {} not found in {}",
+                env_name,
+                environments.display()
+            )
+        })?;
+        all_vars.extend(environment);
+    }
+    all_vars.extend(variables.map_or(HashMap::new(), parse_variables));
+    Ok(all_vars)
+}
+
+/// writes the variables that have a value as yaml, all of them unless `only` is given
+fn save_variables(
+    path: &Path,
+    variables: &HashMap<String, Option<String>>,
+    only: &[String],
+) -> anyhow::Result<()> {
+    let saved: BTreeMap<&String, &String> = variables
+        .iter()
+        .filter(|(name, _)| only.is_empty() || only.contains(name))
+        .filter_map(|(name, value)| value.as_ref().map(|value| (name, value)))
+        .collect();
+    std::fs::write(path, serde_yaml::to_string(&saved)?)
+        .map_err(|err| anyhow::anyhow!("could not write {}: {}", path.display(), err))
+}
+
+/// asks on the terminal for the referenced variables that are not set yet,
+/// empty answers leave the variable unset
+fn prompt_missing_variables(
+    variables: &mut HashMap<String, Option<String>>,
+    referenced: BTreeSet<String>,
+    secrets: &[String],
+) -> anyhow::Result<()> {
+    for name in referenced {
+        if variables.contains_key(&name) {
+            continue;
+        }
+        let prompt = format!("{name}: ");
+        let value = if secrets.contains(&name) {
+            rpassword::prompt_password(prompt)?
+        } else {
+            eprint!("{prompt}");
+            let mut value = String::new();
+            std::io::stdin().read_line(&mut value)?;
+            value.trim_end_matches(['\r', '\n']).to_string()
+        };
+        if !value.is_empty() {
+            variables.insert(name, Some(value));
+        }
+    }
+    Ok(())
+}
+
+/// exit code of runs where a response has an unexpected status or fails its assertions
+const EXIT_FAILED: u8 = 1;
+/// exit code of runs where a request could not be sent or its response not received
+const EXIT_NETWORK: u8 = 2;
+/// exit code of runs that could not start or went wrong otherwise: invalid
+/// arguments, specs, variables or templates, missing files
+const EXIT_CONFIG: u8 = 3;
+
+fn error_exit_code(err: &anyhow::Error) -> ExitCode {
+    let network = err.chain().any(|cause| {
+        matches!(cause.downcast_ref::<reqwest::Error>(), Some(err) if !err.is_builder() && !err.is_status())
+    });
+    if network {
+        ExitCode::from(EXIT_NETWORK)
+    } else if err.chain().any(|cause| cause.is::<UnexpectedResponse>()) {
+        ExitCode::from(EXIT_FAILED)
+    } else {
+        ExitCode::from(EXIT_CONFIG)
+    }
 }
 
 #[tokio::main]
-async fn main() -> anyhow::Result<ExitCode> {
-    let cli = Cli::parse();
+async fn main() -> ExitCode {
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        // help and version
+        Err(err) if !err.use_stderr() => err.exit(),
+        Err(err) => {
+            let _ = err.print();
+            return ExitCode::from(EXIT_CONFIG);
+        }
+    };
 
-    let log_level = match cli.log_level.unwrap_or(2) {
+    init_logging(&cli);
+    let result = if cli.watch {
+        watch(cli).await
+    } else {
+        run(cli).await
+    };
+    match result {
+        Ok(exit_code) => exit_code,
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            error_exit_code(&err)
+        }
+    }
+}
+
+fn init_logging(cli: &Cli) {
+    // json logs are meant for pipelines, which want the outcome of every step
+    let default_level = match cli.log_format {
+        LogFormat::Text => 2,
+        LogFormat::Json => 3,
+    };
+    let log_level = match cli
+        .log_level
+        .unwrap_or(cli.verbose.saturating_add(2).max(default_level))
+    {
         0 => LevelFilter::Off,
         1 => LevelFilter::Error,
         2 => LevelFilter::Warn,
@@ -244,28 +1225,258 @@ async fn main() -> anyhow::Result<ExitCode> {
         _ => LevelFilter::Warn,
     };
 
-    let mut loggers: Vec<Box<dyn simplelog::SharedLogger>> = vec![simplelog::TermLogger::new(
-        log_level,
-        simplelog::Config::default(),
-        simplelog::TerminalMode::Mixed,
-        simplelog::ColorChoice::Auto,
-    )];
+    let terminal_mode = match (cli.output, &cli.command) {
+        (OutputFormat::Json, _) | (_, Command::Import { .. }) => simplelog::TerminalMode::Stderr,
+        (OutputFormat::Markdown, _) => simplelog::TerminalMode::Mixed,
+    };
 
-    if cli.log_file.unwrap_or(false) {
-        loggers.push(simplelog::WriteLogger::new(
+    let mut loggers: Vec<Box<dyn simplelog::SharedLogger>> = match cli.log_format {
+        LogFormat::Text => vec![simplelog::TermLogger::new(
             log_level,
             simplelog::Config::default(),
-            File::create(".climan.log").unwrap(),
-        ));
+            terminal_mode,
+            simplelog::ColorChoice::Auto,
+        )],
+        LogFormat::Json => vec![JsonLogger::new(log_level, Box::new(std::io::stderr()))],
+    };
+
+    if cli.log_file {
+        let file = File::create(".climan.log").unwrap();
+        loggers.push(match cli.log_format {
+            LogFormat::Text => {
+                simplelog::WriteLogger::new(log_level, simplelog::Config::default(), file)
+            }
+            LogFormat::Json => JsonLogger::new(log_level, Box::new(file)),
+        });
     };
 
     simplelog::CombinedLogger::init(loggers).expect("unable to setup logging");
+}
+
+/// combines the results of the runs of a workflow over the records of a data
+/// file, steps are prefixed with their record and failed runs count as a
+/// failed step. The final variables are the ones of the last run.
+fn merge_record_results(results: Vec<anyhow::Result<WorkflowResult>>) -> WorkflowResult {
+    let mut merged = WorkflowResult {
+        responses: Vec::new(),
+        final_variables: HashMap::new(),
+        failed_steps: Vec::new(),
+        passed_steps: Vec::new(),
+        interrupted: false,
+    };
+    for (index, result) in results.into_iter().enumerate() {
+        let record = index + 1;
+        match result {
+            Ok(result) => {
+                merged.responses.extend(result.responses);
+                merged.final_variables = result.final_variables;
+                let prefixed = |step| format!("record {record}: {step}");
+                merged
+                    .failed_steps
+                    .extend(result.failed_steps.into_iter().map(prefixed));
+                merged
+                    .passed_steps
+                    .extend(result.passed_steps.into_iter().map(prefixed));
+                merged.interrupted |= result.interrupted;
+            }
+            Err(err) => {
+                log::error!("record {} failed: {:?}", record, err);
+                merged.failed_steps.push(format!("record {record}"));
+            }
+        }
+    }
+    merged
+}
 
-    let skin: MadSkin = serde_yaml::from_str(include_str!("../assets/skin.yaml"))?;
-    let skinned_on_request =
-        |request: &Request, context: &RequestContext| on_request(skin.clone(), request, context);
+/// the spec files of the command and the files they reference
+fn watched_files(command: &Command) -> anyhow::Result<Vec<PathBuf>> {
+    let path = match command.clone() {
+        Command::Workflow {
+            path,
+            variables,
+            env,
+            env_prefix,
+            env_file,
+            env_name,
+            environments,
+            ..
+        }
+        | Command::Request {
+            path,
+            variables,
+            env,
+            env_prefix,
+            env_file,
+            env_name,
+            environments,
+            ..
+        } if path != STDIN_PATH => {
+            let variables =
+                init_variables(variables, env, env_prefix, env_file, env_name, environments)?;
+            PathBuf::from(spec_path(&path, &variables)?)
+        }
+        _ => return Err(anyhow::anyhow!("--watch needs a workflow or request file")),
+    };
+
+    // specs that cannot be parsed are still watched, so that fixing them triggers a run
+    let referenced = match command {
+        Command::Workflow { .. } => Workflow::load(&path).and_then(|workflow| workflow.files()),
+        _ => read_spec(&path.to_string_lossy()).and_then(|content| {
+            let mut request: Request = spec::from_yaml(&content)?;
+            request.base_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+            Ok(request.files())
+        }),
+    };
+    let mut files = vec![path];
+    files.extend(referenced.unwrap_or_default());
+    Ok(files)
+}
+
+/// runs the command again each time one of its files is saved, until interrupted
+async fn watch(cli: Cli) -> anyhow::Result<ExitCode> {
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        let _ = sender.send(event);
+    })?;
+
+    loop {
+        execute!(std::io::stdout(), Clear(ClearType::All), MoveTo(0, 0))?;
+        match run(cli.clone()).await {
+            // Ctrl-C during the run stops watching too
+            Ok(exit_code) if exit_code == ExitCode::from(EXIT_INTERRUPTED) => return Ok(exit_code),
+            Ok(_) => {}
+            Err(err) => error!("{:?}", err),
+        }
+
+        // editors often replace the files instead of writing them, so their
+        // directories are watched rather than the files themselves
+        let current_dir = env::current_dir()?;
+        let files: Vec<PathBuf> = watched_files(&cli.command)?
+            .iter()
+            .map(|file| current_dir.join(file))
+            .collect();
+        let directories: BTreeSet<&Path> = files.iter().filter_map(|file| file.parent()).collect();
+        for directory in &directories {
+            watcher.watch(directory, RecursiveMode::NonRecursive)?;
+        }
+        println!("\nwatching {} file(s) for changes", files.len());
+
+        loop {
+            let event = tokio::select! {
+                event = receiver.recv() => event,
+                Ok(()) = tokio::signal::ctrl_c() => None,
+            };
+            let event: notify::Event = match event {
+                Some(event) => event?,
+                None => return Ok(ExitCode::SUCCESS),
+            };
+            let changed = (event.kind.is_modify() || event.kind.is_create())
+                && event.paths.iter().any(|path| files.contains(path));
+            if changed {
+                break;
+            }
+        }
+        // a save usually yields several events
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        while receiver.try_recv().is_ok() {}
+
+        for directory in &directories {
+            watcher.unwatch(directory)?;
+        }
+    }
+}
+
+/// the skin of the markdown output: the builtin one, a custom one or none
+fn load_skin(cli: &Cli) -> anyhow::Result<MadSkin> {
+    if cli.no_style {
+        return Ok(MadSkin::no_style());
+    }
+    match &cli.skin {
+        Some(path) => {
+            let content = std::fs::read_to_string(path).map_err(|err| {
+                anyhow::anyhow!("could not read skin {}: {}", path.display(), err)
+            })?;
+            serde_yaml::from_str(&content)
+                .map_err(|err| anyhow::anyhow!("could not parse skin {}: {}", path.display(), err))
+        }
+        None => Ok(serde_yaml::from_str(include_str!("../assets/skin.yaml"))?),
+    }
+}
+
+async fn run(cli: Cli) -> anyhow::Result<ExitCode> {
+    let output = cli.output;
+    let skin = load_skin(&cli)?;
+    let redaction = if cli.no_redact {
+        Redaction::default()
+    } else {
+        Redaction {
+            headers: cli.redact_headers.clone(),
+            secrets: cli.secrets.clone(),
+        }
+    };
+    let header_width = (!cli.full_headers).then_some(cli.header_width as usize);
+    let skinned_on_request = |request: &Request, context: &RequestContext| {
+        match output {
+            OutputFormat::Markdown if cli.quiet && cli.dry_run => {
+                println!("{}", summary_line(&redaction, context, None))
+            }
+            OutputFormat::Markdown if cli.quiet => (),
+            OutputFormat::Markdown => {
+                on_request(skin.clone(), &redaction, header_width, request, context)
+            }
+            OutputFormat::Json if cli.dry_run => print_json_request(&redaction, request, context),
+            OutputFormat::Json => (),
+        }
+        if cli.print_curl {
+            match output {
+                OutputFormat::Markdown => println!("{}\n", curl_command(request, context)),
+                OutputFormat::Json => eprintln!("{}", curl_command(request, context)),
+            }
+        }
+    };
+    let har = HarRecorder::default();
     let skinned_on_response = |request: &Request, context: &RequestContext, response: &Response| {
-        on_response(skin.clone(), request, context, response)
+        if cli.har.is_some() {
+            har.record(context, response);
+        }
+        match output {
+            OutputFormat::Markdown if cli.quiet => {
+                println!("{}", summary_line(&redaction, context, Some(response)))
+            }
+            OutputFormat::Markdown => on_response(
+                skin.clone(),
+                &redaction,
+                header_width,
+                request,
+                context,
+                response,
+            ),
+            OutputFormat::Json => print_json_response(&redaction, request, context, response),
+        }
+    };
+
+    let cookie_jar = Arc::new(match &cli.client.cookie_jar {
+        Some(path) => CookieJar::load(path)?,
+        None => CookieJar::default(),
+    });
+    let fixtures = match (&cli.record, &cli.replay) {
+        (Some(dir), _) => {
+            std::fs::create_dir_all(dir).map_err(|err| {
+                anyhow::anyhow!("could not create directory {}: {}", dir.display(), err)
+            })?;
+            Some(Rc::new(Fixtures::record_to(dir.clone())))
+        }
+        (None, Some(dir)) => Some(Rc::new(Fixtures::replay_from(dir.clone()))),
+        (None, None) => None,
+    };
+    let cache = match &cli.cache {
+        Some(dir) => {
+            std::fs::create_dir_all(dir).map_err(|err| {
+                anyhow::anyhow!("could not create directory {}: {}", dir.display(), err)
+            })?;
+            Some(Rc::new(ResponseCache::new(dir)))
+        }
+        None => None,
     };
 
     match cli.command {
@@ -273,67 +1484,253 @@ async fn main() -> anyhow::Result<ExitCode> {
             path,
             variables,
             files,
+            save_vars,
+            save_only,
+            tags,
+            skip_tags,
+            junit,
+            summary,
+            data,
             env,
+            env_prefix,
+            env_file,
+            env_name,
+            environments,
         } => {
-            let content = std::fs::read_to_string(path)?;
-            let workflow: Workflow = serde_yaml::from_str(&content)?;
-
-            let all_vars = init_variables(variables, env);
-            let client = reqwest::Client::new();
-
-            let workflow_template = TextTemplate::from("# 🚀 Executing workflow: ${name}");
-            let mut workflow_expander = workflow_template.expander();
-            workflow_expander.set("name", &workflow.name);
-
-            skin.print_expander(workflow_expander);
-            let result = workflow
-                .execute(
-                    &client,
-                    all_vars,
-                    files,
-                    &skinned_on_request,
-                    &skinned_on_response,
-                )
-                .await;
-
-            if result.is_err() {
-                log::error!(
-                    "could not execute workflow, error: {:?}",
-                    result.unwrap_err()
-                );
-                Ok(ExitCode::FAILURE)
+            let mut all_vars =
+                init_variables(variables, env, env_prefix, env_file, env_name, environments)?;
+            let path = spec_path(&path, &all_vars)?;
+            let mut workflow = if path == STDIN_PATH {
+                Workflow::parse(&read_spec(&path)?)?
             } else {
-                Ok(ExitCode::SUCCESS)
+                Workflow::load(Path::new(&path))?
+            };
+            workflow.tag_filter = TagFilter { tags, skip_tags };
+            workflow.fixtures = fixtures;
+            workflow.cache = cache;
+            workflow.client_timeouts = cli.client.timeouts();
+            workflow.show_progress = !cli.quiet && std::io::stderr().is_terminal();
+            workflow.tokens = Some(Rc::default());
+            if junit.is_some() && !cli.dry_run {
+                workflow.junit = Some(Rc::default());
+            }
+
+            let templates = Templates::new(cli.strict || workflow.strict.unwrap_or(false));
+            let records = match &data {
+                Some(data) => data::load_records(data)?,
+                None => vec![HashMap::new()],
+            };
+            // variable files are loaded once, before prompting, so that the client
+            // certificate paths can reference them, the records are applied on top
+            all_vars.extend(load_variable_files(files.unwrap_or_default()).await?);
+            if cli.interactive {
+                let mut referenced = workflow.referenced_variables(&templates)?;
+                referenced.retain(|name| !records.iter().any(|record| record.contains_key(name)));
+                prompt_missing_variables(&mut all_vars, referenced, &cli.secrets)?;
+            }
+            let client = build_client(
+                &cli.client,
+                workflow.redirect_policy(),
+                workflow.identity(&templates, &all_vars)?,
+                cookie_jar.clone(),
+            )?;
+            // installed after prompting, so that Ctrl-C still aborts the prompts
+            workflow.interrupt = Some(Interrupt::install());
+
+            if let (OutputFormat::Markdown, false) = (output, cli.quiet) {
+                let workflow_template = TextTemplate::from("# 🚀 Executing workflow: ${name}");
+                let mut workflow_expander = workflow_template.expander();
+                workflow_expander.set("name", &workflow.name);
+
+                skin.print_expander(workflow_expander);
+            }
+            let record_count = records.len();
+            let start = std::time::Instant::now();
+            let mut results = Vec::new();
+            for (index, record) in records.into_iter().enumerate() {
+                if let (Some(_), OutputFormat::Markdown, false) = (&data, output, cli.quiet) {
+                    skin.print_text(&format!("## 📄 Record {}/{}", index + 1, record_count));
+                }
+                let record_vars = data::record_variables(&all_vars, record);
+                let result = if cli.dry_run {
+                    workflow
+                        .dry_run(&client, &templates, record_vars, None, &skinned_on_request)
+                        .await
+                } else {
+                    workflow
+                        .execute(
+                            &client,
+                            &templates,
+                            record_vars,
+                            None,
+                            &skinned_on_request,
+                            &skinned_on_response,
+                        )
+                        .await
+                };
+                let interrupted = matches!(&result, Ok(result) if result.interrupted);
+                results.push(result);
+                if interrupted {
+                    break;
+                }
+            }
+            let result = match data {
+                Some(_) => Ok(merge_record_results(results)),
+                None => results.remove(0),
+            };
+            let duration = start.elapsed();
+
+            if let Some(har_path) = &cli.har {
+                har.write(har_path)?;
+            }
+            if let (Some(junit_path), Some(report)) = (&junit, &workflow.junit) {
+                report.write(junit_path, &workflow.name)?;
+            }
+            if let Some(cookie_jar_path) = &cli.client.cookie_jar {
+                cookie_jar.save(cookie_jar_path)?;
+            }
+
+            if let (Some(save_vars), Ok(result), false) = (&save_vars, &result, cli.dry_run) {
+                save_variables(save_vars, &result.final_variables, &save_only)?;
+            }
+
+            if let Ok(result) = &result {
+                let summary_json = workflow_summary(result, &redaction, duration);
+                if let Some(summary_path) = &summary {
+                    std::fs::write(summary_path, serde_json::to_string_pretty(&summary_json)?)
+                        .map_err(|err| {
+                            anyhow::anyhow!("could not write {}: {}", summary_path.display(), err)
+                        })?;
+                }
+                match output {
+                    OutputFormat::Json => println!("{summary_json}"),
+                    OutputFormat::Markdown if !cli.quiet && !cli.dry_run => {
+                        print_workflow_summary(&skin, &redaction, result, duration)
+                    }
+                    OutputFormat::Markdown => {}
+                }
+            }
+
+            match result {
+                Err(err) => {
+                    log::error!("could not execute workflow, error: {:?}", err);
+                    Ok(error_exit_code(&err))
+                }
+                Ok(result) if result.interrupted => {
+                    log::warn!(
+                        "workflow interrupted, passed steps: {:?}, failed steps: {:?}",
+                        result.passed_steps,
+                        result.failed_steps
+                    );
+                    Ok(ExitCode::from(EXIT_INTERRUPTED))
+                }
+                Ok(result) if !result.failed_steps.is_empty() => {
+                    log::error!("workflow failed steps: {:?}", result.failed_steps);
+                    Ok(ExitCode::from(EXIT_FAILED))
+                }
+                Ok(_) => Ok(ExitCode::SUCCESS),
             }
         }
         Command::Request {
             path,
             variables,
             env,
+            env_prefix,
+            env_file,
+            env_name,
+            environments,
+            repeat,
+            concurrency,
         } => {
-            let content = std::fs::read_to_string(path)?;
-            let request: Request = serde_yaml::from_str(&content)?;
-
-            let all_vars = init_variables(variables, env);
-
-            let client = reqwest::Client::new();
-            let result = request
-                .execute(
-                    &client,
-                    &all_vars,
-                    &skinned_on_request,
-                    &skinned_on_response,
-                )
-                .await;
-
-            if result.is_err() {
-                log::error!(
-                    "could not execute request, error: {:?}",
-                    result.unwrap_err()
-                );
-                Ok(ExitCode::FAILURE)
-            } else {
-                Ok(ExitCode::SUCCESS)
+            let mut all_vars =
+                init_variables(variables, env, env_prefix, env_file, env_name, environments)?;
+            let path = spec_path(&path, &all_vars)?;
+            let mut request: Request = spec::from_yaml(&read_spec(&path)?)
+                .map_err(|err| anyhow::anyhow!("could not parse request {}: {}", path, err))?;
+            request.base_dir = Path::new(&path)
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default();
+            request.fixtures = fixtures;
+            request.cache = cache;
+            request.client_timeouts = cli.client.timeouts();
+            request.show_progress = !cli.quiet && std::io::stderr().is_terminal();
+            request.tokens = Some(Rc::default());
+            request.validate_extractors()?;
+
+            let templates = Templates::new(cli.strict);
+            if cli.interactive {
+                let referenced = request.referenced_variables(&templates);
+                prompt_missing_variables(&mut all_vars, referenced, &cli.secrets)?;
+            }
+
+            let client = build_client(
+                &cli.client,
+                request.redirect_policy(),
+                None,
+                cookie_jar.clone(),
+            )?;
+            // installed after prompting, so that Ctrl-C still aborts the prompts
+            let interrupt = Interrupt::install();
+            if let (Some(times), false) = (repeat, cli.dry_run) {
+                let repeated =
+                    repeat::repeat(&request, &client, &templates, &all_vars, times, concurrency);
+                let stats = tokio::select! {
+                    stats = repeated => stats,
+                    () = interrupt.wait() => {
+                        log::warn!("repeated request interrupted");
+                        return Ok(ExitCode::from(EXIT_INTERRUPTED));
+                    }
+                };
+                print_repeat_stats(output, &stats);
+                return Ok(if stats.succeeded == stats.sent {
+                    ExitCode::SUCCESS
+                } else {
+                    ExitCode::from(EXIT_FAILED)
+                });
+            }
+            let execution = async {
+                if cli.dry_run {
+                    request
+                        .dry_run(&client, &templates, &all_vars, &skinned_on_request)
+                        .await
+                } else {
+                    request
+                        .execute(
+                            &client,
+                            &templates,
+                            &all_vars,
+                            &skinned_on_request,
+                            &skinned_on_response,
+                        )
+                        .await
+                }
+            };
+            let result = tokio::select! {
+                result = execution => result,
+                () = interrupt.wait() => {
+                    log::warn!("request interrupted");
+                    return Ok(ExitCode::from(EXIT_INTERRUPTED));
+                }
+            };
+
+            if let Some(har_path) = &cli.har {
+                har.write(har_path)?;
+            }
+            if let Some(cookie_jar_path) = &cli.client.cookie_jar {
+                cookie_jar.save(cookie_jar_path)?;
+            }
+
+            match result {
+                Err(err) => {
+                    log::error!("could not execute request, error: {:?}", err);
+                    Ok(error_exit_code(&err))
+                }
+                Ok(response) if !response.assertions.failures.is_empty() => {
+                    log::error!("assertions failed: {:?}", response.assertions.failures);
+                    Ok(ExitCode::from(EXIT_FAILED))
+                }
+                Ok(_) => Ok(ExitCode::SUCCESS),
             }
         }
 
@@ -342,5 +1739,55 @@ async fn main() -> anyhow::Result<ExitCode> {
             println!("{}", serde_json::to_string_pretty(&schema).unwrap());
             Ok(ExitCode::SUCCESS)
         }
+
+        Command::Validate { path } => {
+            validate_spec(&path)?;
+            println!("{path} is valid");
+            Ok(ExitCode::SUCCESS)
+        }
+
+        Command::Import { source } => match source {
+            ImportSource::Curl { command, output } => {
+                let request = import::curl_to_request(&command)?;
+                write_spec(&import::to_yaml(&request)?, output)?;
+                Ok(ExitCode::SUCCESS)
+            }
+            ImportSource::Postman { path, output } => {
+                let collection = std::fs::read_to_string(path)?;
+                let workflow = import::postman_to_workflow(&collection)?;
+                write_spec(&import::to_yaml(&workflow)?, output)?;
+                Ok(ExitCode::SUCCESS)
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_mask_secret_variables_in_the_summary() {
+        let result = WorkflowResult {
+            responses: Vec::new(),
+            final_variables: HashMap::from([
+                ("token".to_string(), Some("s3cr3t".to_string())),
+                ("id".to_string(), Some("42".to_string())),
+                ("unset".to_string(), None),
+            ]),
+            failed_steps: Vec::new(),
+            passed_steps: vec!["login".to_string()],
+            interrupted: false,
+        };
+        let redaction = Redaction {
+            headers: Vec::new(),
+            secrets: vec!["token".to_string()],
+        };
+
+        let summary = workflow_summary(&result, &redaction, Duration::from_millis(5));
+        assert_eq!(
+            summary["final_variables"],
+            serde_json::json!({ "token": REDACTED, "id": "42", "unset": null })
+        );
     }
 }